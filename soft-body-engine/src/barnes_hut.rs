@@ -0,0 +1,217 @@
+use glam::Vec2;
+
+// Barnes-Hut quadtree for gravity between every pair of verlets in O(n log n)
+// Far away groups get lumped into their center of mass - https://arborjs.org/docs/barnes-hut
+pub struct QuadTree {
+    nodes: Vec<Node>,
+}
+
+struct Node {
+    min: Vec2,
+    size: f32,
+    mass: f32,
+    weighted_position: Vec2, // Sum of mass * position - divide by mass for the center of mass
+    children: Option<usize>, // Index of the first of 4 children in a row
+    body: Option<usize>,
+    shared: Vec<(usize, Vec2, f32)>, // Every body in a leaf at MAX_DEPTH so the one asking can still leave itself out
+}
+
+// Past this deep bodies sitting on top of each other just share a leaf instead of splitting forever
+const MAX_DEPTH: usize = 32;
+
+impl Node {
+    fn new(min: Vec2, size: f32) -> Self {
+        Node { min, size, mass: 0.0, weighted_position: Vec2::ZERO, children: None, body: None, shared: vec![] }
+    }
+
+    fn center_of_mass(&self) -> Vec2 {
+        self.weighted_position / self.mass
+    }
+
+    // Children go bottom-left, bottom-right, top-left, top-right
+    fn quadrant(&self, position: Vec2) -> usize {
+        let half = self.min + self.size / 2.0;
+        (position.x >= half.x) as usize + 2 * (position.y >= half.y) as usize
+    }
+}
+
+impl QuadTree {
+    // bodies are (position, mass) - the index into it is what acceleration's skip refers to
+    pub fn new(bodies: &[(Vec2, f32)]) -> Self {
+        let (min, max) = bodies.iter().fold(
+            (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+            |(min, max), &(position, _)| (min.min(position), max.max(position)),
+        );
+        // Square so every node splits into squares and a tiny bit bigger so the max corner is still inside
+        let size = (max - min).max_element().max(1.0) * 1.001;
+
+        let mut tree = QuadTree { nodes: vec![Node::new(min, size)] };
+        for (index, &(position, mass)) in bodies.iter().enumerate() {
+            if mass > 0.0 {
+                tree.insert(bodies, index, position, mass);
+            }
+        }
+        tree
+    }
+
+    fn insert(&mut self, bodies: &[(Vec2, f32)], index: usize, position: Vec2, mass: f32) {
+        let mut node = 0;
+        for depth in 0.. {
+            let was_empty = self.nodes[node].mass == 0.0;
+            self.nodes[node].mass += mass;
+            self.nodes[node].weighted_position += position * mass;
+
+            if let Some(first_child) = self.nodes[node].children {
+                node = first_child + self.nodes[node].quadrant(position);
+                continue;
+            }
+            if was_empty {
+                self.nodes[node].body = Some(index);
+                return;
+            }
+            if depth >= MAX_DEPTH {
+                let leaf = &mut self.nodes[node];
+                if let Some(existing) = leaf.body.take() {
+                    leaf.shared.push((existing, bodies[existing].0, bodies[existing].1));
+                }
+                leaf.shared.push((index, position, mass));
+                return;
+            }
+
+            // Leaf already has a body so split it and push that one down a level
+            let (min, size) = (self.nodes[node].min, self.nodes[node].size / 2.0);
+            let first_child = self.nodes.len();
+            for quadrant in 0..4 {
+                let offset = Vec2::new((quadrant % 2) as f32, (quadrant / 2) as f32) * size;
+                self.nodes.push(Node::new(min + offset, size));
+            }
+            self.nodes[node].children = Some(first_child);
+
+            // A shared leaf at MAX_DEPTH never gets here so there's always exactly one body to move
+            if let Some(existing) = self.nodes[node].body.take() {
+                let (existing_position, existing_mass) = bodies[existing];
+                let child = first_child + self.nodes[node].quadrant(existing_position);
+                let child = &mut self.nodes[child];
+                child.mass = existing_mass;
+                child.weighted_position = existing_position * existing_mass;
+                child.body = Some(existing);
+            }
+            node = first_child + self.nodes[node].quadrant(position);
+        }
+    }
+
+    // Gravitational acceleration at position from everything in the tree except body skip
+    // theta is the opening angle - a node whose size / distance is under it counts as one point (0 is exact and 0.5 is the usual)
+    pub fn acceleration(&self, position: Vec2, skip: Option<usize>, gravitational_constant: f32, theta: f32, softening: f32) -> Vec2 {
+        // Softened so two bodies passing right through each other don't get flung off
+        let pull = |offset: Vec2, mass: f32| {
+            let dist_squared = offset.length_squared() + softening * softening;
+            if dist_squared > 0.0 {
+                offset * gravitational_constant * mass / (dist_squared * dist_squared.sqrt())
+            } else {
+                Vec2::ZERO
+            }
+        };
+        let mut acceleration = Vec2::ZERO;
+        let mut stack = vec![0];
+
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            if node.mass == 0.0 {
+                continue;
+            }
+
+            let offset = node.center_of_mass() - position;
+            match node.children {
+                Some(first_child) if node.size * node.size >= theta * theta * offset.length_squared() => {
+                    stack.extend(first_child..first_child + 4);
+                    continue;
+                }
+                None if node.body.is_some() && node.body == skip => continue,
+                // Only split up when skip is in there - otherwise the whole leaf counts as one point like any other node
+                None if node.shared.iter().any(|&(index, _, _)| Some(index) == skip) => {
+                    for &(index, body_position, mass) in &node.shared {
+                        if Some(index) != skip {
+                            acceleration += pull(body_position - position, mass);
+                        }
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+
+            acceleration += pull(offset, node.mass);
+        }
+
+        acceleration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Scattered bodies with a few sitting right on top of each other to hit MAX_DEPTH
+    fn bodies() -> Vec<(Vec2, f32)> {
+        let mut seed: u32 = 12345;
+        let mut next = || {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            (seed >> 8) as f32 / (1 << 24) as f32
+        };
+        let mut bodies: Vec<(Vec2, f32)> = (0..200)
+            .map(|_| (Vec2::new(next() * 400.0 - 200.0, next() * 400.0 - 200.0), next() * 9.0 + 1.0))
+            .collect();
+        bodies.extend([(Vec2::new(10.0, 10.0), 3.0); 3]);
+        bodies
+    }
+
+    fn brute_force(bodies: &[(Vec2, f32)], index: usize, softening: f32) -> Vec2 {
+        let position = bodies[index].0;
+        bodies.iter().enumerate()
+            .filter(|&(other, _)| other != index)
+            .map(|(_, &(other_position, mass))| {
+                let offset = other_position - position;
+                let dist_squared = offset.length_squared() + softening * softening;
+                offset * mass / (dist_squared * dist_squared.sqrt())
+            })
+            .sum()
+    }
+
+    #[test]
+    fn zero_opening_angle_matches_brute_force() {
+        let bodies = bodies();
+        let tree = QuadTree::new(&bodies);
+        for index in 0..bodies.len() {
+            let exact = brute_force(&bodies, index, 1.0);
+            let approximate = tree.acceleration(bodies[index].0, Some(index), 1.0, 0.0, 1.0);
+            assert!((approximate - exact).length() <= 1e-4 * exact.length().max(1e-3), "{index} {approximate} {exact}");
+        }
+    }
+
+    #[test]
+    fn usual_opening_angle_stays_close_to_brute_force() {
+        let bodies = bodies();
+        let tree = QuadTree::new(&bodies);
+        let mut error = 0.0;
+        let mut total = 0.0;
+        for index in 0..200 {
+            let exact = brute_force(&bodies, index, 1.0);
+            error += (tree.acceleration(bodies[index].0, Some(index), 1.0, 0.5, 1.0) - exact).length();
+            total += exact.length();
+        }
+        assert!(error < 0.02 * total, "{error} {total}");
+    }
+
+    // Closer together than a MAX_DEPTH leaf is wide but not on top of each other so a body counting itself would pull towards the pair's middle
+    // Tiny masses keep the pull between them from overflowing
+    #[test]
+    fn shared_leaf_still_skips_the_body_asking() {
+        let bodies = [(Vec2::new(1e-12, 0.0), 1e-20), (Vec2::new(3e-12, 0.0), 2e-20), (Vec2::new(1.0, 1.0), 1e-20)];
+        let tree = QuadTree::new(&bodies);
+        for index in 0..bodies.len() {
+            let exact = brute_force(&bodies, index, 0.0);
+            let approximate = tree.acceleration(bodies[index].0, Some(index), 1.0, 0.0, 0.0);
+            assert!((approximate - exact).length() <= 1e-4 * exact.length(), "{index} {approximate} {exact}");
+        }
+    }
+}
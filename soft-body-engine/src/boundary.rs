@@ -0,0 +1,470 @@
+use std::rc::Rc;
+
+use glam::Vec2;
+use super::sdf::SignedDistanceField;
+
+// The container every verlet is kept inside of
+// Normals handed back always point out of the allowed area so reflecting the velocity along them bounces the verlet back in
+#[derive(Clone, Debug, PartialEq)]
+pub enum Boundary {
+    Circle { center: Vec2, radius: f32 },
+    Box { min: Vec2, max: Vec2 },
+    RotatedBox { center: Vec2, half_extents: Vec2, angle: f32 },
+    Polygon { points: Vec<Vec2> }, // Convex and counter clockwise - use Boundary::polygon to check that
+    Annulus { center: Vec2, inner_radius: f32, outer_radius: f32 }, // The ring between the two circles
+    Periodic { min: Vec2, max: Vec2 }, // No walls - whatever leaves one side comes back in on the other
+    Sdf { field: Rc<SignedDistanceField> }, // Drawn as a mask image - Rc so swapping boundaries around doesn't copy the whole field
+}
+
+// Where the boundary is and how it moves - the Boundary shape itself is in this local frame
+// Only kinematic so nothing the verlets do pushes it back
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BoundaryTransform {
+    pub position: Vec2,
+    pub angle: f32,
+    pub velocity: Vec2,
+    pub angular_velocity: f32,
+}
+
+impl BoundaryTransform {
+    pub fn advance(&mut self, dt: f32) {
+        self.position += self.velocity * dt;
+        self.angle += self.angular_velocity * dt;
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.velocity != Vec2::ZERO || self.angular_velocity != 0.0
+    }
+
+    pub fn to_local(self, point: Vec2) -> Vec2 {
+        Vec2::from_angle(-self.angle).rotate(point - self.position)
+    }
+
+    pub fn to_world(self, point: Vec2) -> Vec2 {
+        self.position + self.rotate_to_world(point)
+    }
+
+    pub fn rotate_to_local(self, vector: Vec2) -> Vec2 {
+        Vec2::from_angle(-self.angle).rotate(vector)
+    }
+
+    pub fn rotate_to_world(self, vector: Vec2) -> Vec2 {
+        Vec2::from_angle(self.angle).rotate(vector)
+    }
+
+    // v + w x r for a point on the wall
+    pub fn velocity_at(&self, point: Vec2) -> Vec2 {
+        self.velocity + self.angular_velocity * (point - self.position).perp()
+    }
+}
+
+impl Boundary {
+    // Checks the points make a convex polygon and flips them to counter clockwise if they were given the other way
+    pub fn polygon(points: &[Vec2]) -> Result<Boundary, String> {
+        Ok(Boundary::Polygon { points: convex_polygon(points)? })
+    }
+
+    // Smallest axis aligned box around the boundary as (min, max) - the solver sizes its grid from this
+    pub fn aabb(&self) -> (Vec2, Vec2) {
+        match self {
+            Boundary::Circle { center, radius } => (*center - *radius, *center + *radius),
+            Boundary::Box { min, max } => (*min, *max),
+            Boundary::RotatedBox { center, half_extents, angle } => {
+                let (sin, cos) = angle.sin_cos();
+                let extents = Vec2::new(
+                    cos.abs() * half_extents.x + sin.abs() * half_extents.y,
+                    sin.abs() * half_extents.x + cos.abs() * half_extents.y,
+                );
+                (*center - extents, *center + extents)
+            }
+            Boundary::Polygon { points } => points.iter().fold(
+                (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+                |(min, max), &point| (min.min(point), max.max(point)),
+            ),
+            Boundary::Annulus { center, outer_radius, .. } => (*center - *outer_radius, *center + *outer_radius),
+            Boundary::Periodic { min, max } => (*min, *max),
+            Boundary::Sdf { field } => field.aabb(),
+        }
+    }
+
+    // aabb once the boundary is placed by transform
+    // A spinning boundary gets a box that fits every angle so the grid doesn't have to change size while it turns
+    pub fn world_aabb(&self, transform: &BoundaryTransform) -> (Vec2, Vec2) {
+        let (min, max) = self.aabb();
+        let corners = [min, Vec2::new(max.x, min.y), max, Vec2::new(min.x, max.y)];
+
+        if transform.angular_velocity != 0.0 {
+            let reach = corners.iter().map(|corner| corner.length()).fold(0.0, f32::max);
+            return (transform.position - reach, transform.position + reach);
+        }
+        corners.iter().fold(
+            (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+            |(min, max), &corner| (min.min(transform.to_world(corner)), max.max(transform.to_world(corner))),
+        )
+    }
+
+    #[allow(dead_code)]
+    pub fn area(&self) -> f32 {
+        match self {
+            Boundary::Circle { radius, .. } => std::f32::consts::PI * radius * radius,
+            Boundary::Box { min, max } => (*max - *min).x * (*max - *min).y,
+            Boundary::RotatedBox { half_extents, .. } => 4.0 * half_extents.x * half_extents.y,
+            Boundary::Polygon { points } => signed_area(points).abs(),
+            Boundary::Annulus { inner_radius, outer_radius, .. } => {
+                std::f32::consts::PI * (outer_radius * outer_radius - inner_radius * inner_radius)
+            }
+            Boundary::Periodic { min, max } => (*max - *min).x * (*max - *min).y,
+            Boundary::Sdf { field } => field.free_area(),
+        }
+    }
+
+    pub fn is_periodic(&self) -> bool {
+        matches!(self, Boundary::Periodic { .. })
+    }
+
+    // Brings a position that left a periodic boundary back in from the other side - every other boundary leaves it alone
+    pub fn wrap(&self, position: Vec2) -> Vec2 {
+        match self {
+            Boundary::Periodic { min, max } => *min + (position - *min).rem_euclid(*max - *min),
+            _ => position,
+        }
+    }
+
+    // Shortest way to get across offset when the boundary wraps around
+    pub fn minimum_image(&self, offset: Vec2) -> Vec2 {
+        match self {
+            Boundary::Periodic { min, max } => {
+                let size = *max - *min;
+                offset - size * (offset / size).round()
+            }
+            _ => offset,
+        }
+    }
+
+    pub fn world_wrap(&self, transform: &BoundaryTransform, position: Vec2) -> Vec2 {
+        transform.to_world(self.wrap(transform.to_local(position)))
+    }
+
+    pub fn world_minimum_image(&self, transform: &BoundaryTransform, offset: Vec2) -> Vec2 {
+        transform.rotate_to_world(self.minimum_image(transform.rotate_to_local(offset)))
+    }
+
+    // If a circle at position pokes out of the boundary gives back where it should be and the normal of the wall it hit
+    // Only the deepest wall gets fixed so a circle stuck in a corner needs a second call for the other wall
+    pub fn contact(&self, position: Vec2, radius: f32) -> Option<(Vec2, Vec2)> {
+        match self {
+            Boundary::Circle { center, radius: boundary_radius } => {
+                circle_contact(position - *center, boundary_radius - radius)
+                    .map(|(corrected, normal)| (*center + corrected, normal))
+            }
+            Boundary::Box { min, max } => {
+                let center = (*min + *max) / 2.0;
+                box_contact(position - center, (*max - *min) / 2.0, radius)
+                    .map(|(corrected, normal)| (center + corrected, normal))
+            }
+            Boundary::RotatedBox { center, half_extents, angle } => {
+                let rotation = Vec2::from_angle(*angle);
+                let local = Vec2::from_angle(-*angle).rotate(position - *center);
+                box_contact(local, *half_extents, radius)
+                    .map(|(corrected, normal)| (*center + rotation.rotate(corrected), rotation.rotate(normal)))
+            }
+            Boundary::Polygon { points } => {
+                let (index, penetration) = deepest_edge(points, position, radius)?;
+                let normal = edge_normal(points, index);
+                Some((position - normal * penetration, normal))
+            }
+            Boundary::Annulus { center, inner_radius, outer_radius } => {
+                let offset = position - *center;
+                if let Some((corrected, normal)) = circle_contact(offset, outer_radius - radius) {
+                    return Some((*center + corrected, normal));
+                }
+
+                let dist = offset.length();
+                let limit = inner_radius + radius;
+                if dist < limit {
+                    // Dead center has no direction to push in so just pick one
+                    let direction = if dist > 0.0 { offset / dist } else { Vec2::X };
+                    return Some((*center + direction * limit, -direction));
+                }
+                None
+            }
+            Boundary::Periodic { .. } => None,
+            Boundary::Sdf { field } => field.contact(position, radius, true).map(|(corrected, normal)| (corrected, -normal)),
+        }
+    }
+
+    // contact with the boundary placed by transform - positions and normals in and out are in world space
+    pub fn world_contact(&self, transform: &BoundaryTransform, position: Vec2, radius: f32) -> Option<(Vec2, Vec2)> {
+        self.contact(transform.to_local(position), radius)
+            .map(|(corrected, normal)| (transform.to_world(corrected), transform.rotate_to_world(normal)))
+    }
+
+    // time_of_exit with the boundary placed by transform - motion should already be relative to the wall
+    pub fn world_time_of_exit(&self, transform: &BoundaryTransform, start: Vec2, motion: Vec2, radius: f32) -> Option<(f32, Vec2)> {
+        self.time_of_exit(transform.to_local(start), transform.rotate_to_local(motion), radius)
+            .map(|(t, normal)| (t, transform.rotate_to_world(normal)))
+    }
+
+    // Swept version of contact for a circle moving start -> start + motion that starts inside
+    // Gives back the fraction of the motion where it first touches a wall and that wall's normal
+    pub fn time_of_exit(&self, start: Vec2, motion: Vec2, radius: f32) -> Option<(f32, Vec2)> {
+        match self {
+            Boundary::Circle { center, radius: boundary_radius } => {
+                let t = time_of_exit(start - *center, motion, boundary_radius - radius)?;
+                Some((t, (start + motion * t - *center).normalize()))
+            }
+            Boundary::Box { min, max } => {
+                let center = (*min + *max) / 2.0;
+                box_time_of_exit(start - center, motion, (*max - *min) / 2.0, radius)
+            }
+            Boundary::RotatedBox { center, half_extents, angle } => {
+                let rotation = Vec2::from_angle(*angle);
+                let inverse = Vec2::from_angle(-*angle);
+                box_time_of_exit(inverse.rotate(start - *center), inverse.rotate(motion), *half_extents, radius)
+                    .map(|(t, normal)| (t, rotation.rotate(normal)))
+            }
+            Boundary::Polygon { points } => {
+                let mut earliest: Option<(f32, Vec2)> = None;
+                for index in 0..points.len() {
+                    let normal = edge_normal(points, index);
+                    let distance = (start - points[index]).dot(normal) + radius;
+                    let speed = motion.dot(normal);
+                    if let Some(t) = plane_time_of_exit(distance, speed)
+                        && earliest.is_none_or(|(best, _)| t < best) {
+                        earliest = Some((t, normal));
+                    }
+                }
+                earliest
+            }
+            Boundary::Annulus { center, inner_radius, outer_radius } => {
+                let offset = start - *center;
+                let outer = time_of_exit(offset, motion, outer_radius - radius)
+                    .map(|t| (t, (offset + motion * t).normalize()));
+                let inner = time_of_impact(offset, motion, inner_radius + radius)
+                    .map(|t| (t, -(offset + motion * t).normalize()));
+                match (outer, inner) {
+                    (Some(outer), Some(inner)) => Some(if inner.0 < outer.0 { inner } else { outer }),
+                    (outer, inner) => outer.or(inner),
+                }
+            }
+            Boundary::Periodic { .. } => None,
+            Boundary::Sdf { field } => field.time_of_impact(start, motion, radius, true).map(|(t, normal)| (t, -normal)),
+        }
+    }
+
+    // Closed loops of points for drawing - the annulus has two and an sdf is a pile of short lines
+    pub fn outline(&self) -> Vec<Vec<Vec2>> {
+        let segments = 64;
+        let circle = |center: Vec2, radius: f32| -> Vec<Vec2> {
+            (0..segments)
+                .map(|i| center + Vec2::from_angle(i as f32 / segments as f32 * std::f32::consts::TAU) * radius)
+                .collect()
+        };
+
+        match self {
+            Boundary::Circle { center, radius } => vec![circle(*center, *radius)],
+            Boundary::Box { min, max } => vec![vec![*min, Vec2::new(max.x, min.y), *max, Vec2::new(min.x, max.y)]],
+            Boundary::RotatedBox { center, half_extents, angle } => {
+                let rotation = Vec2::from_angle(*angle);
+                vec![[(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].iter()
+                    .map(|&(x, y)| *center + rotation.rotate(Vec2::new(x, y) * *half_extents))
+                    .collect()]
+            }
+            Boundary::Polygon { points } => vec![points.clone()],
+            Boundary::Annulus { center, inner_radius, outer_radius } => {
+                vec![circle(*center, *outer_radius), circle(*center, *inner_radius)]
+            }
+            Boundary::Periodic { min, max } => vec![vec![*min, Vec2::new(max.x, min.y), *max, Vec2::new(min.x, max.y)]],
+            Boundary::Sdf { field } => field.contour(true).to_vec(),
+        }
+    }
+}
+
+// Counter clockwise copy of the points or an error if they don't make a convex polygon
+pub fn convex_polygon(points: &[Vec2]) -> Result<Vec<Vec2>, String> {
+    if points.len() < 3 {
+        return Err::<Vec<Vec2>, String>(String::from("Polygon needs at least 3 points"));
+    }
+
+    let mut points = points.to_vec();
+    if signed_area(&points) < 0.0 {
+        points.reverse();
+    }
+
+    let count = points.len();
+    for i in 0..count {
+        let edge = points[(i + 1) % count] - points[i];
+        let next_edge = points[(i + 2) % count] - points[(i + 1) % count];
+        if edge.perp_dot(next_edge) < 0.0 {
+            return Err::<Vec<Vec2>, String>(String::from("Polygon has to be convex"));
+        }
+    }
+
+    Ok(points)
+}
+
+// Shoelace formula - positive when the points go counter clockwise
+pub fn signed_area(points: &[Vec2]) -> f32 {
+    let count = points.len();
+    (0..count).map(|i| points[i].perp_dot(points[(i + 1) % count])).sum::<f32>() / 2.0
+}
+
+// Outward normal of the edge from points[index] to the next point for a counter clockwise polygon
+pub fn edge_normal(points: &[Vec2], index: usize) -> Vec2 {
+    let edge = points[(index + 1) % points.len()] - points[index];
+    Vec2::new(edge.y, -edge.x).normalize()
+}
+
+// The edge a circle sticks out of the most and by how much
+fn deepest_edge(points: &[Vec2], position: Vec2, radius: f32) -> Option<(usize, f32)> {
+    let mut deepest: Option<(usize, f32)> = None;
+    for index in 0..points.len() {
+        let penetration = (position - points[index]).dot(edge_normal(points, index)) + radius;
+        if penetration > 0.0 && deepest.is_none_or(|(_, best)| penetration > best) {
+            deepest = Some((index, penetration));
+        }
+    }
+    deepest
+}
+
+// offset is from the center and limit is how far the center of the verlet is allowed to go
+fn circle_contact(offset: Vec2, limit: f32) -> Option<(Vec2, Vec2)> {
+    if offset.length() > limit {
+        let normal = offset.normalize();
+        Some((normal * limit, normal))
+    } else {
+        None
+    }
+}
+
+// Box centered on the origin in its own frame
+fn box_contact(local: Vec2, half_extents: Vec2, radius: f32) -> Option<(Vec2, Vec2)> {
+    let limit = half_extents - radius;
+    let penetration = local.abs() - limit;
+    if penetration.x <= 0.0 && penetration.y <= 0.0 {
+        return None;
+    }
+
+    if penetration.x > penetration.y {
+        let normal = Vec2::new(local.x.signum(), 0.0);
+        Some((Vec2::new(normal.x * limit.x, local.y), normal))
+    } else {
+        let normal = Vec2::new(0.0, local.y.signum());
+        Some((Vec2::new(local.x, normal.y * limit.y), normal))
+    }
+}
+
+fn box_time_of_exit(start: Vec2, motion: Vec2, half_extents: Vec2, radius: f32) -> Option<(f32, Vec2)> {
+    let limit = half_extents - radius;
+    let mut earliest: Option<(f32, Vec2)> = None;
+    for normal in [Vec2::X, Vec2::NEG_X, Vec2::Y, Vec2::NEG_Y] {
+        let distance = start.dot(normal) - limit.dot(normal.abs());
+        if let Some(t) = plane_time_of_exit(distance, motion.dot(normal))
+            && earliest.is_none_or(|(best, _)| t < best) {
+            earliest = Some((t, normal));
+        }
+    }
+    earliest
+}
+
+// distance is how far past the wall we start (negative is inside) and speed is how fast that grows over the motion
+fn plane_time_of_exit(distance: f32, speed: f32) -> Option<f32> {
+    if distance > 0.0 || speed <= 0.0 {
+        return None;
+    }
+    let t = -distance / speed;
+    if (0.0..1.0).contains(&t) {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+// Solves |start + motion * t| = distance for the first t in [0, 1] where two circles start touching
+// start and motion are relative to the other circle
+pub fn time_of_impact(start: Vec2, motion: Vec2, distance: f32) -> Option<f32> {
+    let a = motion.dot(motion);
+    let b = 2.0 * start.dot(motion);
+    let c = start.dot(start) - distance * distance;
+
+    // Already overlapping is solve_collisions' job and no relative motion means no hit
+    if c < 0.0 || a == 0.0 {
+        return None;
+    }
+
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let t = (-b - discriminant.sqrt()) / (2.0 * a);
+    if (0.0..=1.0).contains(&t) {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+// Same equation but for leaving a circle of radius distance around the origin so we want the larger root
+pub fn time_of_exit(start: Vec2, motion: Vec2, distance: f32) -> Option<f32> {
+    let a = motion.dot(motion);
+    let b = 2.0 * start.dot(motion);
+    let c = start.dot(start) - distance * distance;
+
+    if c > 0.0 || a == 0.0 {
+        return None;
+    }
+
+    let discriminant = b * b - 4.0 * a * c;
+    let t = (-b + discriminant.max(0.0).sqrt()) / (2.0 * a);
+    if (0.0..1.0).contains(&t) {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec2;
+    use super::*;
+
+    #[test]
+    fn time_of_impact_finds_the_first_touch() {
+        // Closing 20 at 40 apart with a touching distance of 10
+        let t = time_of_impact(vec2(40.0, 0.0), vec2(-40.0, 0.0), 10.0).unwrap();
+        assert!((t - 0.75).abs() < 1e-6);
+        // Glancing past 5 off center still clips it
+        let t = time_of_impact(vec2(40.0, 5.0), vec2(-80.0, 0.0), 10.0).unwrap();
+        let touch = vec2(40.0, 5.0) + vec2(-80.0, 0.0) * t;
+        assert!((touch.length() - 10.0).abs() < 1e-4 && touch.x > 0.0);
+        // Misses, moves away, stops short or already overlaps
+        assert_eq!(time_of_impact(vec2(40.0, 20.0), vec2(-80.0, 0.0), 10.0), None);
+        assert_eq!(time_of_impact(vec2(40.0, 0.0), vec2(40.0, 0.0), 10.0), None);
+        assert_eq!(time_of_impact(vec2(40.0, 0.0), vec2(-20.0, 0.0), 10.0), None);
+        assert_eq!(time_of_impact(vec2(5.0, 0.0), vec2(-20.0, 0.0), 10.0), None);
+        assert_eq!(time_of_impact(vec2(40.0, 0.0), Vec2::ZERO, 10.0), None);
+    }
+
+    #[test]
+    fn time_of_exit_finds_where_it_leaves() {
+        // From the middle of a circle of 10 out past its edge
+        let t = time_of_exit(Vec2::ZERO, vec2(20.0, 0.0), 10.0).unwrap();
+        assert!((t - 0.5).abs() < 1e-6);
+        // Starting off center and going across takes the far side
+        let t = time_of_exit(vec2(-5.0, 0.0), vec2(20.0, 0.0), 10.0).unwrap();
+        assert!((t - 0.75).abs() < 1e-6);
+        // Stays inside, starts outside or doesn't move
+        assert_eq!(time_of_exit(Vec2::ZERO, vec2(5.0, 0.0), 10.0), None);
+        assert_eq!(time_of_exit(vec2(20.0, 0.0), vec2(-5.0, 0.0), 10.0), None);
+        assert_eq!(time_of_exit(Vec2::ZERO, Vec2::ZERO, 10.0), None);
+    }
+
+    #[test]
+    fn box_exit_normal_is_the_wall_it_hits() {
+        let boundary = Boundary::Box { min: vec2(-100.0, -100.0), max: vec2(100.0, 100.0) };
+        let (t, normal) = boundary.time_of_exit(vec2(0.0, 50.0), vec2(0.0, 100.0), 10.0).unwrap();
+        assert!((t - 0.4).abs() < 1e-6);
+        assert_eq!(normal, vec2(0.0, 1.0));
+    }
+}
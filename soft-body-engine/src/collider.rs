@@ -0,0 +1,173 @@
+use std::rc::Rc;
+
+use glam::Vec2;
+use super::boundary::{convex_polygon, edge_normal};
+use super::sdf::SignedDistanceField;
+
+// Obstacles that never move - ramps, funnels, pegs
+#[derive(Clone, Debug, PartialEq)]
+pub enum ColliderShape {
+    #[allow(dead_code)]
+    Segment { start: Vec2, end: Vec2 },
+    Capsule { start: Vec2, end: Vec2, radius: f32 }, // A segment with thickness and round ends
+    Polygon { points: Vec<Vec2> }, // Convex and counter clockwise - use ColliderShape::polygon to check that
+    Sdf { field: Rc<SignedDistanceField> }, // Any shape drawn as a mask image - outside the image is free space
+}
+
+impl ColliderShape {
+    pub fn polygon(points: &[Vec2]) -> Result<ColliderShape, String> {
+        Ok(ColliderShape::Polygon { points: convex_polygon(points)? })
+    }
+
+    // A container can be all free space but an obstacle with nothing solid in it would never be hit
+    pub fn sdf(field: Rc<SignedDistanceField>) -> Result<ColliderShape, String> {
+        if !field.has_solid() {
+            return Err::<ColliderShape, String>(String::from("Obstacle mask needs at least 1 solid pixel"));
+        }
+        Ok(ColliderShape::Sdf { field })
+    }
+
+    pub fn aabb(&self) -> (Vec2, Vec2) {
+        match self {
+            ColliderShape::Segment { start, end } => (start.min(*end), start.max(*end)),
+            ColliderShape::Capsule { start, end, radius } => (start.min(*end) - *radius, start.max(*end) + *radius),
+            ColliderShape::Polygon { points } => points.iter().fold(
+                (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+                |(min, max), &point| (min.min(point), max.max(point)),
+            ),
+            ColliderShape::Sdf { field } => field.aabb(),
+        }
+    }
+
+    // If a circle at position overlaps the shape gives back where it should be and the normal pointing away from the shape
+    pub fn contact(&self, position: Vec2, radius: f32) -> Option<(Vec2, Vec2)> {
+        match self {
+            ColliderShape::Segment { start, end } => segment_contact(*start, *end, position, radius),
+            ColliderShape::Capsule { start, end, radius: capsule_radius } => {
+                segment_contact(*start, *end, position, radius + capsule_radius)
+            }
+            ColliderShape::Polygon { points } => {
+                // Separating axis - the edge the center is furthest in front of
+                let mut deepest = (0, f32::NEG_INFINITY);
+                for index in 0..points.len() {
+                    let separation = (position - points[index]).dot(edge_normal(points, index));
+                    if separation > deepest.1 {
+                        deepest = (index, separation);
+                    }
+                }
+
+                let (index, separation) = deepest;
+                if separation <= 0.0 {
+                    // Center is inside so push it out through the closest edge
+                    let normal = edge_normal(points, index);
+                    return Some((position + normal * (radius - separation), normal));
+                }
+                if separation >= radius {
+                    return None;
+                }
+
+                // Outside but close - the closest point could be on any edge or a corner
+                let count = points.len();
+                (0..count)
+                    .map(|i| closest_point_on_segment(points[i], points[(i + 1) % count], position))
+                    .min_by(|a, b| a.distance_squared(position).total_cmp(&b.distance_squared(position)))
+                    .and_then(|closest| push_out(closest, position, radius, edge_normal(points, index)))
+            }
+            ColliderShape::Sdf { field } => field.contact(position, radius, false),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Collider {
+    shape: ColliderShape,
+    restitution: f32,
+    friction: f32,
+}
+
+impl Collider {
+    // restitution is how much of the normal velocity bounces back and friction is the coulomb coefficient
+    pub fn new(shape: ColliderShape, restitution: f32, friction: f32) -> Self {
+        Collider {
+            shape,
+            restitution,
+            friction,
+        }
+    }
+
+    pub fn get_shape(&self) -> &ColliderShape {
+        &self.shape
+    }
+
+    #[allow(dead_code)]
+    pub fn get_restitution(&self) -> f32 {
+        self.restitution
+    }
+
+    #[allow(dead_code)]
+    pub fn set_restitution(&mut self, restitution: f32) {
+        self.restitution = restitution;
+    }
+
+    #[allow(dead_code)]
+    pub fn get_friction(&self) -> f32 {
+        self.friction
+    }
+
+    #[allow(dead_code)]
+    pub fn set_friction(&mut self, friction: f32) {
+        self.friction = friction;
+    }
+
+    // Velocity after hitting the collider - normal points away from it
+    pub fn bounce(&self, velocity: Vec2, normal: Vec2) -> Vec2 {
+        bounce(velocity, normal, self.restitution, self.friction)
+    }
+}
+
+// Velocity after hitting a surface with the normal pointing away from it
+// Friction can take away at most friction * normal impulse from the sliding speed and never reverses it
+pub fn bounce(velocity: Vec2, normal: Vec2, restitution: f32, friction: f32) -> Vec2 {
+    let normal_speed = velocity.dot(normal);
+    if normal_speed >= 0.0 {
+        return velocity; // Already moving away
+    }
+
+    let tangent_velocity = velocity - normal * normal_speed;
+    let tangent_speed = tangent_velocity.length();
+    let normal_impulse = -normal_speed * (1.0 + restitution);
+    let tangent_velocity = if tangent_speed > 0.0 {
+        tangent_velocity * (1.0 - (friction * normal_impulse / tangent_speed).min(1.0))
+    } else {
+        tangent_velocity
+    };
+
+    tangent_velocity - normal * normal_speed * restitution
+}
+
+fn closest_point_on_segment(start: Vec2, end: Vec2, position: Vec2) -> Vec2 {
+    let edge = end - start;
+    let length_squared = edge.length_squared();
+    if length_squared == 0.0 {
+        return start;
+    }
+    let t = ((position - start).dot(edge) / length_squared).clamp(0.0, 1.0);
+    start + edge * t
+}
+
+fn segment_contact(start: Vec2, end: Vec2, position: Vec2, distance: f32) -> Option<(Vec2, Vec2)> {
+    let closest = closest_point_on_segment(start, end, position);
+    // Sitting exactly on the line has no direction so use the side the segment's normal is on
+    let fallback = (end - start).perp().try_normalize().unwrap_or(Vec2::Y);
+    push_out(closest, position, distance, fallback)
+}
+
+// Pushes position to distance away from closest if it's nearer than that
+fn push_out(closest: Vec2, position: Vec2, distance: f32, fallback: Vec2) -> Option<(Vec2, Vec2)> {
+    let offset = position - closest;
+    if offset.length_squared() >= distance * distance {
+        return None;
+    }
+    let normal = offset.try_normalize().unwrap_or(fallback);
+    Some((closest + normal * distance, normal))
+}
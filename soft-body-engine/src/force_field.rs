@@ -0,0 +1,183 @@
+use glam::Vec2;
+
+// Anything that pushes verlets around depending on where they are and how fast they go
+// The solver adds every field on top of gravity and samples them inside the integrator so RK4 gets its in between states too
+pub trait ForceField {
+    // Acceleration on a verlet - mass is there for fields that are really forces like drag
+    fn acceleration(&self, position: Vec2, velocity: Vec2, mass: f32) -> Vec2;
+
+    // Called once every substep before integrating for fields that change over time
+    fn update(&mut self, _dt: f32) {}
+}
+
+// Pulls everything within range towards position - negative strength pushes away instead
+// Strongest in the middle and fades out linearly to nothing at range
+pub struct PointAttractor {
+    pub position: Vec2,
+    pub strength: f32,
+    pub range: f32,
+}
+
+impl PointAttractor {
+    pub fn new(position: Vec2, strength: f32, range: f32) -> Self {
+        PointAttractor { position, strength, range }
+    }
+}
+
+impl ForceField for PointAttractor {
+    fn acceleration(&self, position: Vec2, _velocity: Vec2, _mass: f32) -> Vec2 {
+        let offset = self.position - position;
+        let dist = offset.length();
+        if dist >= self.range || dist == 0.0 {
+            return Vec2::ZERO;
+        }
+        offset / dist * self.strength * (1.0 - dist / self.range)
+    }
+}
+
+// Inverse square pull like a planet - strength is G * M
+// softening stops it blowing up when something gets right on top of the center
+pub struct RadialGravity {
+    pub center: Vec2,
+    pub strength: f32,
+    pub softening: f32,
+}
+
+impl RadialGravity {
+    pub fn new(center: Vec2, strength: f32, softening: f32) -> Self {
+        RadialGravity { center, strength, softening }
+    }
+}
+
+impl ForceField for RadialGravity {
+    fn acceleration(&self, position: Vec2, _velocity: Vec2, _mass: f32) -> Vec2 {
+        let offset = self.center - position;
+        let dist_squared = offset.length_squared() + self.softening * self.softening;
+        offset * self.strength / (dist_squared * dist_squared.sqrt())
+    }
+}
+
+// Swirls everything within range counter clockwise around center - negative strength goes clockwise
+pub struct Vortex {
+    pub center: Vec2,
+    pub strength: f32,
+    pub range: f32,
+}
+
+impl Vortex {
+    pub fn new(center: Vec2, strength: f32, range: f32) -> Self {
+        Vortex { center, strength, range }
+    }
+}
+
+impl ForceField for Vortex {
+    fn acceleration(&self, position: Vec2, _velocity: Vec2, _mass: f32) -> Vec2 {
+        let offset = position - self.center;
+        let dist = offset.length();
+        if dist >= self.range || dist == 0.0 {
+            return Vec2::ZERO;
+        }
+        offset.perp() / dist * self.strength * (1.0 - dist / self.range)
+    }
+}
+
+// F = -(linear * v + quadratic * |v| * v) - linear is like moving through syrup and quadratic like moving through air
+pub struct Drag {
+    pub linear: f32,
+    pub quadratic: f32,
+}
+
+impl Drag {
+    pub fn new(linear: f32, quadratic: f32) -> Self {
+        Drag { linear, quadratic }
+    }
+}
+
+impl ForceField for Drag {
+    fn acceleration(&self, _position: Vec2, velocity: Vec2, mass: f32) -> Vec2 {
+        -(self.linear + self.quadratic * velocity.length()) * velocity / mass
+    }
+}
+
+// Drags verlets towards the wind velocity like linear drag in a moving medium
+// The wind speed goes up and down in bands wavelength apart that travel along with it so it comes in gusts
+pub struct Wind {
+    pub velocity: Vec2,
+    pub coefficient: f32,
+    pub variation: f32, // 0 is steady and 1 means the gusts go from nothing to double speed
+    pub wavelength: f32,
+    time: f32,
+}
+
+impl Wind {
+    pub fn new(velocity: Vec2, coefficient: f32, variation: f32, wavelength: f32) -> Self {
+        Wind { velocity, coefficient, variation, wavelength, time: 0.0 }
+    }
+
+    pub fn velocity_at(&self, position: Vec2) -> Vec2 {
+        let speed = self.velocity.length();
+        if speed == 0.0 || self.wavelength == 0.0 {
+            return self.velocity;
+        }
+        let along = position.dot(self.velocity / speed) - speed * self.time;
+        self.velocity * (1.0 + self.variation * (along / self.wavelength * std::f32::consts::TAU).sin())
+    }
+}
+
+impl ForceField for Wind {
+    fn acceleration(&self, position: Vec2, velocity: Vec2, mass: f32) -> Vec2 {
+        (self.velocity_at(position) - velocity) * self.coefficient / mass
+    }
+
+    fn update(&mut self, dt: f32) {
+        self.time += dt;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec2;
+    use super::*;
+
+    #[test]
+    fn attractor_fades_out_at_range() {
+        let attractor = PointAttractor::new(vec2(10.0, 0.0), 100.0, 50.0);
+        let pull = attractor.acceleration(vec2(-15.0, 0.0), vec2(0.0, 0.0), 1.0);
+        assert!((pull - vec2(50.0, 0.0)).length() < 1e-4, "{pull}");
+        assert_eq!(attractor.acceleration(vec2(10.0, 60.0), vec2(0.0, 0.0), 1.0), Vec2::ZERO);
+        assert_eq!(attractor.acceleration(vec2(10.0, 0.0), vec2(0.0, 0.0), 1.0), Vec2::ZERO);
+
+        let repeller = PointAttractor::new(vec2(10.0, 0.0), -100.0, 50.0);
+        let push = repeller.acceleration(vec2(-15.0, 0.0), vec2(0.0, 0.0), 1.0);
+        assert!((push - vec2(-50.0, 0.0)).length() < 1e-4, "{push}");
+    }
+
+    #[test]
+    fn radial_gravity_is_inverse_square() {
+        let planet = RadialGravity::new(vec2(0.0, 0.0), 1.0e6, 1.0);
+        let near = planet.acceleration(vec2(0.0, 100.0), vec2(0.0, 0.0), 1.0);
+        let far = planet.acceleration(vec2(0.0, 200.0), vec2(0.0, 0.0), 1.0);
+        assert!(near.x == 0.0 && near.y < 0.0, "{near}");
+        assert!((near.length() - 100.0).abs() < 0.1, "{near}");
+        assert!((near.length() / far.length() - 4.0).abs() < 0.01, "{near} {far}");
+        // Softening keeps the middle finite
+        assert!(planet.acceleration(vec2(0.0, 0.0), vec2(0.0, 0.0), 1.0).is_finite());
+    }
+
+    #[test]
+    fn wind_pushes_towards_its_velocity() {
+        let steady = Wind::new(vec2(100.0, 0.0), 2.0, 0.0, 50.0);
+        assert_eq!(steady.acceleration(vec2(30.0, 10.0), vec2(100.0, 0.0), 4.0), Vec2::ZERO);
+        let push = steady.acceleration(vec2(30.0, 10.0), vec2(0.0, 20.0), 4.0);
+        assert!((push - vec2(50.0, -10.0)).length() < 1e-4, "{push}");
+
+        // Gusts go between nothing and double speed and travel along with the wind
+        let mut gusty = Wind::new(vec2(100.0, 0.0), 2.0, 1.0, 40.0);
+        assert!((gusty.velocity_at(vec2(10.0, 0.0)) - vec2(200.0, 0.0)).length() < 1e-3);
+        assert!(gusty.velocity_at(vec2(30.0, 0.0)).length() < 1e-3);
+        let before = gusty.velocity_at(vec2(10.0, 0.0));
+        gusty.update(0.25);
+        let after = gusty.velocity_at(vec2(35.0, 0.0));
+        assert!((before - after).length() < 1e-3, "{before} {after}");
+    }
+}
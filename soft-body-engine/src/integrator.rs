@@ -0,0 +1,10 @@
+// Which scheme the solver uses to move every verlet forward in time
+// Position verlet is the classic one this engine started with and the explicit velocity ones are here to compare accuracy
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Integrator {
+    #[default]
+    PositionVerlet, // x' = x + (x - x_last) * dt / last_dt + a dt^2
+    VelocityVerlet, // x' = x + v dt + a dt^2 / 2 and v gets the average of the acceleration before and after
+    SemiImplicitEuler, // v' = v + a dt then x' = x + v' dt - symplectic so orbits don't spiral out
+    Rk4, // Samples the force field 4 times per step - best for smooth fields like planets
+}
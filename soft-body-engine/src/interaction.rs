@@ -0,0 +1,260 @@
+use glam::Vec2;
+use super::solver::Solver;
+
+// What the mouse (or a finger or anything else that points) does to the simulation
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Tool {
+    #[default]
+    Spawn, // Left to the front-end since it knows what it wants to drop
+    Drag, // Grab the closest verlet and pull it around on a spring
+    Explode, // One radial kick outwards when pressed
+    Attract,
+    Repel,
+    Erase, // Removes everything under the cursor while held
+}
+
+// Knows nothing about input - the front-end says where the cursor is in world space and whether it's down
+// Everything is done as velocity changes once a frame so it works the same however many substeps the solver takes
+pub struct Interaction {
+    tool: Tool,
+    radius: f32,
+    strength: f32,
+    spring_stiffness: f32,
+    spring_damping: f32,
+    grabbed: Option<usize>,
+    press_pending: bool, // Pressed on a frame nothing got simulated in so it still counts on the next one
+}
+
+impl Interaction {
+    // radius is how far the tools reach and strength is the explosion speed or the attract / repel acceleration
+    pub fn new(radius: f32, strength: f32) -> Self {
+        Interaction {
+            tool: Tool::default(),
+            radius,
+            strength,
+            spring_stiffness: 200.0,
+            spring_damping: 20.0,
+            grabbed: None,
+            press_pending: false,
+        }
+    }
+
+    pub fn get_tool(&self) -> Tool {
+        self.tool
+    }
+
+    pub fn set_tool(&mut self, tool: Tool) {
+        self.tool = tool;
+        self.grabbed = None;
+    }
+
+    pub fn get_radius(&self) -> f32 {
+        self.radius
+    }
+
+    #[allow(dead_code)]
+    pub fn set_radius(&mut self, radius: f32) {
+        self.radius = radius;
+    }
+
+    #[allow(dead_code)]
+    pub fn get_strength(&self) -> f32 {
+        self.strength
+    }
+
+    #[allow(dead_code)]
+    pub fn set_strength(&mut self, strength: f32) {
+        self.strength = strength;
+    }
+
+    // Stiffness and damping per unit mass so light and heavy verlets follow the cursor the same
+    #[allow(dead_code)]
+    pub fn set_spring(&mut self, spring_stiffness: f32, spring_damping: f32) {
+        self.spring_stiffness = spring_stiffness;
+        self.spring_damping = spring_damping;
+    }
+
+    pub fn get_grabbed(&self) -> Option<usize> {
+        self.grabbed
+    }
+
+    // Call once a frame - just_pressed is only true on the frame the button went down
+    // dt is how much time the solver simulated this frame so pushes add up the same at any frame rate
+    pub fn update(&mut self, solver: &mut Solver, cursor: Vec2, down: bool, just_pressed: bool, dt: f32) {
+        // Merging and erasing shift indices down so follow the grabbed verlet to wherever it ended up
+        if let Some(remap) = solver.take_index_remap() {
+            self.grabbed = self.grabbed.and_then(|index| remap.get(index).copied().flatten());
+        }
+
+        if !down {
+            self.grabbed = None;
+            self.press_pending = false;
+            return;
+        }
+        // Velocities are stored over a dt so with none there's nothing to push with yet
+        if dt <= 0.0 {
+            self.press_pending |= just_pressed;
+            return;
+        }
+        let just_pressed = just_pressed || std::mem::take(&mut self.press_pending);
+
+        match self.tool {
+            Tool::Spawn => {}
+            Tool::Drag => {
+                if just_pressed {
+                    self.grabbed = self.closest(solver, cursor);
+                }
+                // Gone if it got erased or merged into something
+                let Some(verlet) = self.grabbed.and_then(|index| solver.get_verlets_mut().get_mut(index)) else {
+                    self.grabbed = None;
+                    return;
+                };
+                // Waking first since it zeroes the velocity
+                if verlet.is_sleeping() {
+                    verlet.set_sleeping(false);
+                }
+                // Implicit spring so a long frame can't make it overshoot and blow up
+                let velocity = (verlet.get_velocity() + (cursor - verlet.get_position()) * self.spring_stiffness * dt)
+                    / (1.0 + self.spring_damping * dt + self.spring_stiffness * dt * dt);
+                verlet.set_velocity(velocity, dt);
+            }
+            Tool::Explode => {
+                if just_pressed {
+                    // Strength is a change in speed here so the whole thing happens in one go
+                    self.push(solver, cursor, self.strength, dt);
+                }
+            }
+            Tool::Attract => self.push(solver, cursor, -self.strength * dt, dt),
+            Tool::Repel => self.push(solver, cursor, self.strength * dt, dt),
+            Tool::Erase => {
+                let radius_squared = self.radius * self.radius;
+                let under_cursor: Vec<usize> = solver.get_verlets().iter().enumerate()
+                    .filter(|(_, verlet)| verlet.get_position().distance_squared(cursor) < radius_squared)
+                    .map(|(i, _)| i)
+                    .collect();
+                if !under_cursor.is_empty() && let Err(e) = solver.remove_verlets(&under_cursor) {
+                    println!("Error erasing: {}", e);
+                }
+            }
+        }
+    }
+
+    // Closest verlet within radius that isn't anchored
+    fn closest(&self, solver: &Solver, cursor: Vec2) -> Option<usize> {
+        solver.get_verlets().iter().enumerate()
+            .filter(|(_, verlet)| !verlet.is_anchored())
+            .map(|(i, verlet)| (i, verlet.get_position().distance_squared(cursor)))
+            .filter(|&(_, dist_squared)| dist_squared < self.radius * self.radius)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _)| i)
+    }
+
+    // Changes speed by up to speed outwards (inwards when negative) fading out to nothing at radius
+    fn push(&self, solver: &mut Solver, cursor: Vec2, speed: f32, dt: f32) {
+        for verlet in solver.get_verlets_mut() {
+            if verlet.is_anchored() {
+                continue;
+            }
+            let offset = verlet.get_position() - cursor;
+            let dist = offset.length();
+            if dist >= self.radius || dist == 0.0 {
+                continue;
+            }
+            if verlet.is_sleeping() {
+                verlet.set_sleeping(false);
+            }
+            verlet.add_velocity(offset / dist * speed * (1.0 - dist / self.radius), dt);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec2;
+    use super::*;
+    use super::super::verlet::Verlet;
+    use super::super::boundary::Boundary;
+    use super::super::merging::MergeRules;
+
+    fn ball(position: Vec2) -> Verlet {
+        let mut verlet = Verlet::new(position);
+        verlet.set_radius(10.0);
+        verlet
+    }
+
+    #[test]
+    fn erasing_under_a_sleeping_pile_drops_the_rest() {
+        let mut solver = Solver::new(&[], vec2(0.0, -100.0), 200.0, 8, 25.0, 10000.0);
+        solver.set_boundary(Boundary::Box { min: vec2(-100.0, -100.0), max: vec2(100.0, 100.0) });
+        solver.set_sleeping_enabled(true, 5.0, 0.5);
+        for y in 0..3 {
+            for x in 0..10 {
+                solver.add_position(ball(vec2(x as f32 * 20.0 - 90.0, y as f32 * 20.0 - 90.0)));
+            }
+        }
+        for _ in 0..180 {
+            solver.update(1.0 / 60.0);
+        }
+        assert_eq!(solver.get_sleeping_count(), 30);
+
+        // Takes out the bottom middle of the pile
+        let mut interaction = Interaction::new(35.0, 0.0);
+        interaction.set_tool(Tool::Erase);
+        interaction.update(&mut solver, vec2(0.0, -90.0), true, true, 1.0 / 60.0);
+        let erased = 30 - solver.get_verlets().len();
+        assert!(erased > 0);
+
+        // Everything over the hole falls into it - the walls bounce perfectly so only how low they got says anything
+        let over_hole: Vec<usize> = (0..solver.get_verlets().len())
+            .filter(|&i| solver.get_verlets()[i].get_position().x.abs() < 20.0)
+            .collect();
+        assert!(!over_hole.is_empty());
+        let mut lowest = f32::INFINITY;
+        for _ in 0..120 {
+            solver.update(1.0 / 60.0);
+            for &i in &over_hole {
+                lowest = lowest.min(solver.get_verlets()[i].get_position().y);
+            }
+        }
+        assert!(lowest < -85.0, "lowest over the hole {lowest}");
+    }
+
+    #[test]
+    fn grab_follows_its_verlet_through_a_merge() {
+        let mut solver = Solver::new(&[], Vec2::ZERO, 200.0, 8, 25.0, 10000.0);
+        solver.set_merge_rules(Some(MergeRules::new(f32::INFINITY, f32::INFINITY)));
+        solver.add_position(ball(vec2(-50.0, 0.0)));
+        solver.add_position(ball(vec2(-35.0, 0.0)));
+        solver.add_position(ball(vec2(50.0, 0.0)));
+
+        let mut interaction = Interaction::new(20.0, 0.0);
+        interaction.set_tool(Tool::Drag);
+        interaction.update(&mut solver, vec2(50.0, 0.0), true, true, 1.0 / 60.0);
+        assert_eq!(interaction.get_grabbed(), Some(2));
+
+        solver.update(1.0 / 60.0);
+        assert_eq!(solver.get_verlets().len(), 2);
+        interaction.update(&mut solver, vec2(60.0, 0.0), true, false, 1.0 / 60.0);
+        assert_eq!(interaction.get_grabbed(), Some(1));
+        for _ in 0..60 {
+            solver.update(1.0 / 60.0);
+            interaction.update(&mut solver, vec2(60.0, 0.0), true, false, 1.0 / 60.0);
+        }
+        assert!(solver.get_verlets()[1].get_position().distance(vec2(60.0, 0.0)) < 1.0);
+        assert!(solver.get_verlets()[0].get_position().x < 0.0);
+    }
+
+    #[test]
+    fn press_without_simulated_time_waits_for_the_next_frame() {
+        let mut solver = Solver::new(&[ball(vec2(10.0, 0.0))], Vec2::ZERO, 200.0, 8, 25.0, 10000.0);
+        let mut interaction = Interaction::new(50.0, 100.0);
+        interaction.set_tool(Tool::Explode);
+
+        interaction.update(&mut solver, Vec2::ZERO, true, true, 0.0);
+        assert_eq!(solver.get_verlets()[0].get_velocity(), Vec2::ZERO);
+
+        interaction.update(&mut solver, Vec2::ZERO, true, false, 1.0 / 60.0);
+        let velocity = solver.get_verlets()[0].get_velocity();
+        assert!((velocity - vec2(80.0, 0.0)).length() < 1e-3, "{velocity}");
+    }
+}
@@ -1,14 +1,40 @@
 mod solver;
 mod verlet;
+mod shape_match;
+mod rigid_body;
+mod soft_body;
+mod integrator;
+mod stepper;
+mod boundary;
+mod collider;
+mod sdf;
+mod force_field;
+mod barnes_hut;
+mod pair_potential;
+mod sph;
+mod pbf;
+mod thermal;
+mod merging;
+mod interaction;
 
 use solver::Solver;
 use verlet::Verlet;
+use soft_body::BodyOptions;
+use stepper::Stepper;
+use boundary::Boundary;
+use collider::{Collider, ColliderShape};
+use sdf::SignedDistanceField;
+use force_field::{Drag, PointAttractor, RadialGravity, Vortex, Wind};
+use pair_potential::PairPotential;
+use sph::Sph;
+use pbf::Pbf;
+use thermal::{Thermal, temperature_color};
+use merging::MergeRules;
+use interaction::{Interaction, Tool};
+use integrator::Integrator;
 
-use macroquad::prelude::{clear_background, draw_circle, draw_circle_lines, draw_text, get_fps, is_key_pressed, is_mouse_button_down, mouse_position, next_frame, screen_height, screen_width, Color, KeyCode, MouseButton, BLACK, RED, WHITE, GREEN, draw_line};
-use glam::vec2;
-
-use std::time::Instant;
-
+use macroquad::prelude::{clear_background, draw_circle, draw_text, get_fps, is_key_pressed, is_mouse_button_down, is_mouse_button_pressed, mouse_position, draw_circle_lines, next_frame, screen_height, screen_width, Color, KeyCode, MouseButton, BLACK, RED, WHITE, GREEN, draw_line};
+use glam::{vec2, vec4};
 
 #[macroquad::main("Game")]
 async fn main() {
@@ -31,31 +57,43 @@ async fn main() {
         ball_size * 2.5,
         10000.0,
     );
+    solver.set_skip_constrained_collisions(true); // Stops the cloth fighting its own springs
+    solver.set_sleeping_enabled(true, 5.0, 0.5); // Resting balls stop costing anything after half a second
+    solver.set_coulomb(1.0e4, ball_size * 6.0); // Only does anything once some charged balls get dropped in
+    // Right click pours water - half size balls packed a ball apart
+    let fluid_spacing = ball_size;
+    let fluid_mass = std::f32::consts::PI * (fluid_spacing / 2.0).powi(2); // What a drop of radius fluid_spacing / 2 weighs at the default density
+    let fluid_rest_density = fluid_mass / (fluid_spacing * fluid_spacing);
+    solver.set_sph(Some(Sph::new(fluid_spacing * 2.0, fluid_rest_density, 1.0e6, 200.0)));
     if let Err(e) = solver.load_colors("colors.bin") {
         println!("Error loading colors: {}", e);
     }
 
-    let start_time = Instant::now();
-
-    let dt = 4;  // 1 / 60.0 = 16.6 ms
-    let mut accumulator = 0;
+    // B cycles through these - all roughly the size of the starting circle
+    let boundaries = [
+        Boundary::Circle { center: vec2(0.0, 0.0), radius: constraint_radius },
+        Boundary::Box { min: vec2(-constraint_radius, -constraint_radius), max: vec2(constraint_radius, constraint_radius) },
+        Boundary::RotatedBox { center: vec2(0.0, 0.0), half_extents: vec2(constraint_radius, constraint_radius * 0.6), angle: 0.3 },
+        Boundary::polygon(&(0..6).map(|i| glam::Vec2::from_angle(i as f32 * std::f32::consts::TAU / 6.0) * constraint_radius).collect::<Vec<_>>()).unwrap(),
+        Boundary::Annulus { center: vec2(0.0, 0.0), inner_radius: constraint_radius * 0.3, outer_radius: constraint_radius },
+    ];
+    let mut boundary_index = 0;
 
-    let mouse_drops_per_ms = 100;
-    let mut mouse_drop_accumulator = 0;
+    let mut stepper = Stepper::new(0.004, 16).unwrap(); // 4 ms steps and at most 16 of them a frame
 
-    let ball_drop_per_frame = 10;
-    let mut ball_drop_accumlator = 0;
+    let mouse_drop_interval = 0.1;
+    let mut mouse_drop_accumulator = 0.0;
+    let mut interaction = Interaction::new(ball_size * 8.0, 400.0); // 1 - 6 pick the tool the left mouse button uses
 
-    let mut last_time = start_time.elapsed().as_millis();
-    let mut total_time: u128 = 0;
+    let force_field_names = ["None", "Vortex", "Wind", "Planet", "Attractor"];
+    let mut force_field_index = 0;
+    let mut body_index = 0; // C drops a box, a wheel and a rope in turn
 
     let fps_threshold: i32 = 60;
     let measurement_frames: i32 = 30; // Number of frames to confirm slowdown
     let mut slow_frames_accumulator: i32 = 0;
     let mut balls_til_60_fps: usize = 0;
 
-    let mut angle_degree = 0;
-
     // CUBE
     // if solver.create_distance_constraints(&[
     //     (0, 1, 100.0),
@@ -69,104 +107,263 @@ async fn main() {
     // }
     
     // Create a cloth grid
-    let grid_width = 10;
-    let grid_height = 10;
-    let spacing = 20.0; // Distance between particles
-
-    // Create the particles
-    let mut cloth_particles = Vec::new();
-    for y in 0..grid_height {
-        for x in 0..grid_width {
-            let x_pos = (x as f32 * spacing) - (grid_width as f32 * spacing / 2.0);
-            let y_pos = (y as f32 * spacing);
-            
-            let mut particle = Verlet::new(vec2(x_pos, y_pos));
-            particle.set_radius(ball_size / 2.0); // Smaller radius for cloth
-            
-            // Optional: anchor the top row
-            
-            cloth_particles.push(particle);
+    let cloth_options = BodyOptions {
+        spacing: 20.0, // Distance between particles
+        radius: ball_size / 2.0, // Smaller radius for cloth
+        shear_springs: true, // Diagonal connections for more stability
+        bend_springs: false,
+        pinned: vec![], // Optional: anchor the top row with (0..10).collect()
+        collision_group: 0,
+    };
+    solver.create_cloth(vec2(-100.0, 180.0), 10, 10, &cloth_options).unwrap();
+
+    // Jelly blob held together by shape matching instead of springs
+    let jelly_size = 5;
+    let jelly_start = solver.get_verlets().len();
+    for y in 0..jelly_size {
+        for x in 0..jelly_size {
+            let mut particle = Verlet::new(vec2(-250.0 + x as f32 * ball_size * 1.2, 50.0 + y as f32 * ball_size * 1.2));
+            particle.set_radius(ball_size / 2.0);
+            solver.add_position(particle);
         }
     }
+    let jelly_indices: Vec<usize> = (jelly_start..solver.get_verlets().len()).collect();
+    solver.create_shape_match(&jelly_indices, 0.3).unwrap();
 
-    // Add all particles to the solver
-    let start_index = solver.get_verlets().len();
-    solver.add_positions(&mut cloth_particles);
-
-    // Create structural constraints (horizontal and vertical)
-    let mut constraints = Vec::new();
-    for y in 0..grid_height {
-        for x in 0..grid_width {
-            let idx = start_index + y * grid_width + x;
-            
-            // Horizontal connections
-            if x < grid_width - 1 {
-                let right_idx = idx + 1;
-                constraints.push((idx, right_idx, spacing));
-            }
-            
-            // Vertical connections
-            if y < grid_height - 1 {
-                let bottom_idx = idx + grid_width;
-                constraints.push((idx, bottom_idx, spacing));
-            }
-            
-            // Optional: Diagonal connections for more stability
-            if x < grid_width - 1 && y < grid_height - 1 {
-                let bottom_right_idx = idx + grid_width + 1;
-                constraints.push((idx, bottom_right_idx, spacing * 1.414)); // sqrt(2) ≈ 1.414
-            }
-            
-            if x > 0 && y < grid_height - 1 {
-                let bottom_left_idx = idx + grid_width - 1;
-                constraints.push((idx, bottom_left_idx, spacing * 1.414));
-            }
-        }
+    // Rigid L shape made out of circles
+    let rigid_start = solver.get_verlets().len();
+    for offset in [vec2(0.0, 0.0), vec2(0.0, 1.0), vec2(0.0, 2.0), vec2(1.0, 0.0)] {
+        let mut particle = Verlet::new(vec2(200.0, 50.0) + offset * ball_size * 2.0);
+        particle.set_radius(ball_size);
+        solver.add_position(particle);
     }
+    let rigid_indices: Vec<usize> = (rigid_start..solver.get_verlets().len()).collect();
+    solver.create_rigid_body(&rigid_indices).unwrap();
+
+    // A ramp on the left and a wedge on the right
+    solver.add_collider(Collider::new(
+        ColliderShape::Capsule { start: vec2(-0.8, -0.2) * constraint_radius, end: vec2(-0.3, -0.45) * constraint_radius, radius: 4.0 },
+        0.3,
+        0.2,
+    ));
+    solver.add_collider(Collider::new(
+        ColliderShape::polygon(&[vec2(0.3, -0.7) * constraint_radius, vec2(0.7, -0.7) * constraint_radius, vec2(0.5, -0.4) * constraint_radius]).unwrap(),
+        0.5,
+        0.1,
+    ));
 
-    solver.create_distance_constraints(&constraints).unwrap();
-    
     loop {
-        let current_time = start_time.elapsed().as_millis();
-        let frame_time = current_time - last_time; // Maybe add a cap to stop death dpiral
-        let fps = 1.0 / (frame_time as f32 / 1000.0); // Maybe implement smoothing FPS
-        last_time = current_time;
+        let steps = stepper.step(&mut solver);
+        // The first frame has no frame time to go off
+        let fps = if stepper.get_frame_time() > 0.0 { 1.0 / stepper.get_frame_time() } else { 0.0 }; // Maybe implement smoothing FPS
+
+        mouse_drop_accumulator += stepper.get_frame_time();
+
+        let cursor = (vec2(mouse_position().0, mouse_position().1) - vec2(screen_width / 2.0, screen_height / 2.0)) * vec2(1.0, -1.0);
+        interaction.update(&mut solver, cursor, is_mouse_button_down(MouseButton::Left), is_mouse_button_pressed(MouseButton::Left), steps as f32 * stepper.get_dt());
+
+        if interaction.get_tool() == Tool::Spawn && is_mouse_button_down(MouseButton::Left) && mouse_drop_accumulator >= mouse_drop_interval {
+            let mut ball = Verlet::new(cursor);
+            ball.set_radius(ball_size);
+
+            solver.add_position(ball);
+            mouse_drop_accumulator = 0.0;
+        }
+        if is_mouse_button_down(MouseButton::Right) && mouse_drop_accumulator >= mouse_drop_interval {
+            let position = (vec2(mouse_position().0, mouse_position().1) - vec2(screen_width / 2.0, screen_height / 2.0)) * vec2(1.0, -1.0);
+            for y in 0..3 {
+                for x in 0..3 {
+                    let mut drop = Verlet::new(position + vec2(x as f32 - 1.0, y as f32 - 1.0) * fluid_spacing);
+                    drop.set_radius(fluid_spacing / 2.0);
+                    drop.set_fluid(true);
+                    solver.add_position(drop);
+                }
+            }
+            mouse_drop_accumulator = 0.0;
+        }
         
-        accumulator += frame_time;
-        mouse_drop_accumulator += frame_time;
-
-        while accumulator >= dt {
-            solver.update(dt as f32 / 1000.0);
-            accumulator -= dt;
-            total_time += dt;
-            ball_drop_accumlator += 1;
-
-            // if ball_drop_accumlator >= ball_drop_per_frame && !solver.is_container_full() {
-            //     for _ in 0..1 {
-            //         let angle = angle_degree as f32 / 180.0 * std::f32::consts::PI;
-            //         let angle_vec = vec2(angle.cos(), angle.sin());
-            //         let mut ball = Verlet::new(constraint_radius * 0.98 * angle_vec);
-            //         ball.set_radius(ball_size);
-            //         ball.set_velocity(-100.0 * angle_vec, dt as f32 / 1000.0);
-            //         solver.add_position(ball);
-            //         angle_degree = (angle_degree % 360) + 3;
-            //     }
-
-            //     ball_drop_accumlator = 0;
-            // }
-        }
-
-        if is_mouse_button_down(MouseButton::Left) {
-            if mouse_drop_accumulator >= mouse_drops_per_ms {
-                let position = (vec2(mouse_position().0, mouse_position().1) - vec2(screen_width / 2.0, screen_height / 2.0)) * vec2(1.0, -1.0);
-                let mut ball = Verlet::new(position);
-                ball.set_radius(ball_size);
-
-                solver.add_position(ball);
-                mouse_drop_accumulator = 0;
+        for (key, tool) in [
+            (KeyCode::Key1, Tool::Spawn),
+            (KeyCode::Key2, Tool::Drag),
+            (KeyCode::Key3, Tool::Explode),
+            (KeyCode::Key4, Tool::Attract),
+            (KeyCode::Key5, Tool::Repel),
+            (KeyCode::Key6, Tool::Erase),
+        ] {
+            if is_key_pressed(key) {
+                interaction.set_tool(tool);
+            }
+        }
+
+        // Fast ball straight down that would tunnel without swept collisions
+        if is_key_pressed(KeyCode::F) {
+            let mut ball = Verlet::new(vec2(0.0, constraint_radius - ball_size * 2.0));
+            ball.set_radius(ball_size);
+            ball.set_continuous_collision(true);
+            ball.set_velocity(vec2(0.0, -2000.0), stepper.get_dt());
+            solver.add_position(ball);
+        }
+
+        // Ghost ball at the mouse on its own layer - it falls through the other balls but still lands on walls and colliders
+        if is_key_pressed(KeyCode::H) {
+            let mut ghost = Verlet::new(cursor);
+            ghost.set_radius(ball_size);
+            ghost.set_collision_layer(2);
+            ghost.set_collision_mask(0);
+            solver.add_position(ghost);
+        }
+
+        // Soft bodies at the mouse - the rope starts at the cursor and runs off to the right
+        if is_key_pressed(KeyCode::C) {
+            let options = BodyOptions { spacing: ball_size * 2.0, radius: ball_size / 2.0, bend_springs: true, ..BodyOptions::default() };
+            let body = match body_index {
+                0 => solver.create_box(cursor, 4, 4, &options),
+                1 => solver.create_circle(cursor, ball_size * 4.0, 12, &options),
+                _ => solver.create_rope(cursor, cursor + vec2(ball_size * 12.0, 0.0), 12, &options),
             };
+            if let Err(e) = body {
+                println!("Error creating soft body: {}", e);
+            }
+            body_index = (body_index + 1) % 3;
         }
-        
+
+        if is_key_pressed(KeyCode::B) {
+            boundary_index = (boundary_index + 1) % boundaries.len();
+            solver.set_boundary(boundaries[boundary_index].clone());
+        }
+
+        // Periodic box with no gravity for watching a gas - P again goes back to the current boundary with gravity
+        if is_key_pressed(KeyCode::P) {
+            if solver.get_boundary().is_periodic() {
+                solver.set_boundary(boundaries[boundary_index].clone());
+                solver.set_gravity(vec2(0.0, -100.0));
+            } else {
+                solver.set_boundary(Boundary::Periodic { min: vec2(-constraint_radius, -constraint_radius), max: vec2(constraint_radius, constraint_radius) });
+                solver.set_gravity(vec2(0.0, 0.0));
+            }
+        }
+
+        // Block of alternating + (red) and - (blue) balls at the mouse that should hold together like salt
+        if is_key_pressed(KeyCode::I) {
+            let position = (vec2(mouse_position().0, mouse_position().1) - vec2(screen_width / 2.0, screen_height / 2.0)) * vec2(1.0, -1.0);
+            for y in 0..6 {
+                for x in 0..6 {
+                    let mut ion = Verlet::new(position + vec2(x as f32 - 2.5, y as f32 - 2.5) * ball_size * 2.0);
+                    ion.set_radius(ball_size);
+                    let charge = if (x + y) % 2 == 0 { 100.0 } else { -100.0 };
+                    ion.set_charge(charge);
+                    ion.set_color(if charge > 0.0 { vec4(230.0, 60.0, 60.0, 1.0) } else { vec4(60.0, 90.0, 230.0, 1.0) });
+                    solver.add_position(ion);
+                }
+            }
+        }
+
+        // Swaps SPH for position based fluids which want big steps instead of lots of small ones
+        if is_key_pressed(KeyCode::W) {
+            if solver.get_pbf().is_some() {
+                solver.set_sph(Some(Sph::new(fluid_spacing * 2.0, fluid_rest_density, 1.0e6, 200.0)));
+                solver.set_subdivision(8);
+            } else {
+                solver.set_pbf(Some(Pbf::new(fluid_spacing * 2.0, fluid_rest_density, 4)));
+                solver.set_subdivision(2);
+            }
+        }
+
+        // Hot floor and air that cools things back down - balls start at the ambient 0 so they don't float or sink
+        if is_key_pressed(KeyCode::T) {
+            if solver.get_thermal().is_some() {
+                solver.set_thermal(None);
+            } else {
+                let mut thermal = Thermal::new(3.0, 0.0, 0.2);
+                thermal.cooling = 0.5;
+                thermal.add_wall_heat(vec2(0.0, -1.0), 50.0, 20.0, ball_size * 1.5);
+                solver.set_thermal(Some(thermal));
+            }
+        }
+
+        // Gentle bumps stick together and hard hits shatter - try it with N for planets
+        if is_key_pressed(KeyCode::G) {
+            if solver.get_merge_rules().is_some() {
+                solver.set_merge_rules(None);
+            } else {
+                let mut merge_rules = MergeRules::new(30.0, 400.0);
+                merge_rules.max_radius = ball_size * 5.0;
+                merge_rules.min_radius = ball_size * 0.4;
+                solver.set_merge_rules(Some(merge_rules));
+            }
+        }
+
+        // Cycles plain balls -> wet sand -> Lennard-Jones
+        if is_key_pressed(KeyCode::J) {
+            solver.set_pair_potential(match solver.get_pair_potential() {
+                PairPotential::None => PairPotential::Cohesion { strength: 1.0e5, band: ball_size * 0.5 },
+                PairPotential::Cohesion { .. } => PairPotential::LennardJones { epsilon: 1.0e6, cutoff: 2.2 },
+                PairPotential::LennardJones { .. } => PairPotential::None,
+            });
+        }
+
+        // Magnetic field out of the screen so moving ions curl around
+        if is_key_pressed(KeyCode::M) {
+            solver.set_magnetic_field(if solver.get_magnetic_field() == 0.0 { 50.0 } else { 0.0 });
+        }
+
+        // Everything pulls on everything instead of falling down - planets
+        if is_key_pressed(KeyCode::N) {
+            let n_body_gravity = !solver.is_n_body_gravity();
+            solver.set_n_body_gravity(n_body_gravity, 20.0, 0.5, ball_size);
+            solver.set_gravity(if n_body_gravity { vec2(0.0, 0.0) } else { vec2(0.0, -100.0) });
+        }
+
+        // Cycles whirlpool -> gusty wind -> planet -> attractor -> nothing
+        if is_key_pressed(KeyCode::V) {
+            force_field_index = (force_field_index + 1) % force_field_names.len();
+            solver.clear_force_fields();
+            match force_field_index {
+                // Some drag so it doesn't spin up forever
+                1 => {
+                    solver.add_force_field(Box::new(Vortex::new(vec2(0.0, 0.0), 400.0, constraint_radius)));
+                    solver.add_force_field(Box::new(Drag::new(100.0, 0.0)));
+                }
+                2 => {
+                    solver.add_force_field(Box::new(Wind::new(vec2(150.0, 0.0), 200.0, 0.5, constraint_radius)));
+                }
+                // Pulls into the middle instead of down - about 100 at the edge like normal gravity
+                3 => {
+                    solver.set_gravity(vec2(0.0, 0.0));
+                    solver.add_force_field(Box::new(RadialGravity::new(vec2(0.0, 0.0), 100.0 * constraint_radius * constraint_radius, ball_size * 2.0)));
+                }
+                // Gravity comes back and the middle holds up whatever falls near it
+                4 => {
+                    solver.set_gravity(vec2(0.0, -100.0));
+                    solver.add_force_field(Box::new(PointAttractor::new(vec2(0.0, 0.0), 300.0, constraint_radius * 0.5)));
+                }
+                _ => {}
+            }
+        }
+
+        // Cycles the integrators to compare them - try it with N where RK4 keeps orbits tidiest
+        if is_key_pressed(KeyCode::E) {
+            solver.set_integrator(match solver.get_integrator() {
+                Integrator::PositionVerlet => Integrator::VelocityVerlet,
+                Integrator::VelocityVerlet => Integrator::SemiImplicitEuler,
+                Integrator::SemiImplicitEuler => Integrator::Rk4,
+                Integrator::Rk4 => Integrator::PositionVerlet,
+            });
+        }
+
+        // Substeps follow the fastest ball instead of always doing 8
+        if is_key_pressed(KeyCode::A) {
+            let adaptive = !solver.is_adaptive_subdivision();
+            solver.set_adaptive_subdivision(adaptive, 32, 0.5);
+        }
+
+        // Spin the container like a drum - friction is what drags the balls along with the wall
+        if is_key_pressed(KeyCode::R) {
+            let spinning = solver.get_boundary_transform().angular_velocity != 0.0;
+            solver.set_wall_friction(if spinning { 0.0 } else { 0.3 });
+            solver.set_boundary_velocity(vec2(0.0, 0.0), if spinning { 0.0 } else { 1.0 });
+        }
+
         if is_key_pressed(KeyCode::S) {
             if let Err(e) = solver.save_colors("colors.bin") {
                 println!("Error saving colors: {}", e);
@@ -174,25 +371,96 @@ async fn main() {
                 println!("Colors saved successfully!");
             }
         }
-        if is_key_pressed(KeyCode::L) {
-            if let Err(e) = solver.color_from_image("churros.png") {
-                println!("Error loading image: {}", e);
+        if is_key_pressed(KeyCode::L) && let Err(e) = solver.color_from_image("churros.png") {
+            println!("Error loading image: {}", e);
+        }
+        // Level drawn in black on white stretched over the starting circle's box
+        if is_key_pressed(KeyCode::K) {
+            if !std::path::Path::new("level.png").exists() {
+                println!("No level.png to load - draw one in black on white next to where this is run from");
+            } else {
+                match SignedDistanceField::from_image("level.png", vec2(-constraint_radius, -constraint_radius), vec2(constraint_radius, constraint_radius)) {
+                    Ok(field) => solver.set_boundary(Boundary::Sdf { field: std::rc::Rc::new(field) }),
+                    Err(e) => println!("Error loading level: {}", e),
+                }
+            }
+            // Whatever is black in obstacle.png gets dropped in on top as a collider
+            if std::path::Path::new("obstacle.png").exists() {
+                let shape = SignedDistanceField::from_image("obstacle.png", vec2(-constraint_radius, -constraint_radius), vec2(constraint_radius, constraint_radius))
+                    .map_err(|e| e.to_string())
+                    .and_then(|field| ColliderShape::sdf(std::rc::Rc::new(field)));
+                match shape {
+                    Ok(shape) => {
+                        solver.add_collider(Collider::new(shape, 0.3, 0.2));
+                    }
+                    Err(e) => println!("Error loading obstacle: {}", e),
+                }
             }
         }
         
         clear_background(BLACK);
-        draw_circle_lines(screen_width / 2.0, screen_height / 2.0, constraint_radius, 1.0, WHITE);
+        let origin = vec2(screen_width / 2.0, screen_height / 2.0);
+        for outline in solver.get_boundary_outline() {
+            for (i, &point) in outline.iter().enumerate() {
+                let (x1, y1) = (origin + point * vec2(1.0, -1.0)).into();
+                let (x2, y2) = (origin + outline[(i + 1) % outline.len()] * vec2(1.0, -1.0)).into();
+                draw_line(x1, y1, x2, y2, 1.0, WHITE);
+            }
+        }
 
-        let alpha = accumulator as f32 / dt as f32;
-        for verlet in solver.get_verlets() {
+        for collider in solver.get_colliders() {
+            match collider.get_shape() {
+                ColliderShape::Segment { start, end } => {
+                    let (x1, y1) = (origin + *start * vec2(1.0, -1.0)).into();
+                    let (x2, y2) = (origin + *end * vec2(1.0, -1.0)).into();
+                    draw_line(x1, y1, x2, y2, 1.0, WHITE);
+                }
+                ColliderShape::Capsule { start, end, radius } => {
+                    let (x1, y1) = (origin + *start * vec2(1.0, -1.0)).into();
+                    let (x2, y2) = (origin + *end * vec2(1.0, -1.0)).into();
+                    draw_line(x1, y1, x2, y2, radius * 2.0, WHITE);
+                    draw_circle(x1, y1, *radius, WHITE);
+                    draw_circle(x2, y2, *radius, WHITE);
+                }
+                ColliderShape::Polygon { points } => {
+                    for (i, &point) in points.iter().enumerate() {
+                        let (x1, y1) = (origin + point * vec2(1.0, -1.0)).into();
+                        let (x2, y2) = (origin + points[(i + 1) % points.len()] * vec2(1.0, -1.0)).into();
+                        draw_line(x1, y1, x2, y2, 1.0, WHITE);
+                    }
+                }
+                ColliderShape::Sdf { field } => {
+                    for line in field.contour(false) {
+                        let (x1, y1) = (origin + line[0] * vec2(1.0, -1.0)).into();
+                        let (x2, y2) = (origin + line[1] * vec2(1.0, -1.0)).into();
+                        draw_line(x1, y1, x2, y2, 1.0, WHITE);
+                    }
+                }
+            }
+        }
+
+        let alpha = stepper.get_alpha();
+        for (i, verlet) in solver.get_verlets().iter().enumerate() {
             // This is since the solver imagines the ball at being shows at 0, 0
             let origin = vec2(screen_width / 2.0, screen_height / 2.0);
             let interpolated_pos = origin + verlet.get_interpolated_position(alpha) * vec2(1.0, -1.0);
             let (x, y) = interpolated_pos.into();
+            // Water is drawn bigger so it looks like one body and gets lighter where it's squashed
+            if verlet.is_fluid() && (solver.get_sph().is_some() || solver.get_pbf().is_some()) {
+                let density = solver.get_fluid_densities().get(i).copied().unwrap_or(fluid_rest_density);
+                let squash = ((density / fluid_rest_density - 1.0) * 5.0).clamp(0.0, 1.0);
+                draw_circle(x, y, fluid_spacing, Color::new(0.1 + 0.5 * squash, 0.3 + 0.5 * squash, 0.9, 1.0));
+                continue;
+            }
+            if verlet.get_collision_mask() == 0 {
+                draw_circle_lines(x, y, verlet.get_radius(), 1.0, WHITE);
+                continue;
+            }
+            let color = if solver.get_thermal().is_some() { temperature_color(verlet.get_temperature(), 0.0, 50.0) } else { verlet.get_color() };
             draw_circle(x, y, verlet.get_radius(), Color::from_rgba(
-                verlet.get_color().x as u8,
-                verlet.get_color().y as u8,
-                verlet.get_color().z as u8,
+                color.x as u8,
+                color.y as u8,
+                color.z as u8,
                 255
             ));
         }
@@ -207,6 +475,16 @@ async fn main() {
             draw_line(x1, y1, x2, y2, 1.0, if (inter_pos1 - inter_pos2).length() < distance { RED } else { GREEN });
         }
 
+        if interaction.get_tool() != Tool::Spawn {
+            let (x, y) = (origin + cursor * vec2(1.0, -1.0)).into();
+            draw_circle_lines(x, y, interaction.get_radius(), 1.0, WHITE);
+        }
+        if let Some(grabbed) = interaction.get_grabbed().and_then(|index| solver.get_verlets().get(index)) {
+            let (x1, y1) = (origin + grabbed.get_interpolated_position(alpha) * vec2(1.0, -1.0)).into();
+            let (x2, y2) = (origin + cursor * vec2(1.0, -1.0)).into();
+            draw_line(x1, y1, x2, y2, 1.0, WHITE);
+        }
+
         if get_fps() < fps_threshold && balls_til_60_fps == 0 {
             slow_frames_accumulator += 1;
             if slow_frames_accumulator >= measurement_frames {
@@ -221,11 +499,35 @@ async fn main() {
                 "FPS: {fps:.0}",
             ),
             &format!(
-                "time: {:.3}", total_time as f32 / 1000.0
+                "time: {:.3}", stepper.get_total_time()
+            ),
+            &format!(
+                "dropped time: {:.3}", stepper.get_dropped_time()
             ),
             &format!(
                 "Verlets: {}", solver.get_verlets().len()
             ),
+            &format!(
+                "Sleeping: {}", solver.get_sleeping_count()
+            ),
+            &format!(
+                "Pair potential: {:?}", solver.get_pair_potential()
+            ),
+            &format!(
+                "Fluid: {}", if solver.get_pbf().is_some() { "PBF" } else { "SPH" }
+            ),
+            &format!(
+                "Tool: {:?}", interaction.get_tool()
+            ),
+            &format!(
+                "Integrator: {:?}", solver.get_integrator()
+            ),
+            &format!(
+                "Force field: {} ({} active)", force_field_names[force_field_index], solver.get_force_fields().len()
+            ),
+            &format!(
+                "Substeps: {}{}", solver.get_last_subdivision(), if solver.is_adaptive_subdivision() { " (adaptive)" } else { "" }
+            ),
             &format!(
                 "60 fps ball count: {balls_til_60_fps}"
             ),
@@ -0,0 +1,33 @@
+// When two free verlets hit each other they can stick into one or the bigger one can break apart
+// Only verlets that aren't anchored, fluid or part of a constraint, shape match or rigid body ever merge or split
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MergeRules {
+    pub merge_speed: f32, // Pairs closing slower than this become one verlet - 0 never merges and INFINITY always does
+    pub max_radius: f32, // Merges that would grow past this just bounce instead
+    pub split_speed: f32, // Impacts faster than this shatter the bigger verlet - INFINITY never splits
+    pub fragments: usize, // How many pieces a split makes
+    pub min_radius: f32, // Verlets whose pieces would be smaller than this don't split
+    pub fragment_speed: f32, // Fraction of the impact speed the pieces fly apart with
+}
+
+impl MergeRules {
+    pub fn new(merge_speed: f32, split_speed: f32) -> Self {
+        MergeRules {
+            merge_speed,
+            max_radius: f32::INFINITY,
+            split_speed,
+            fragments: 3,
+            min_radius: 2.0,
+            fragment_speed: 0.25,
+        }
+    }
+
+    // Pieces keep the total area so they keep the total mass at the same density
+    pub fn fragment_radius(&self, radius: f32) -> f32 {
+        radius / (self.fragments.max(1) as f32).sqrt()
+    }
+
+    pub fn can_split(&self, radius: f32) -> bool {
+        self.fragments >= 2 && self.fragment_radius(radius) >= self.min_radius
+    }
+}
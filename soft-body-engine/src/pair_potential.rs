@@ -0,0 +1,86 @@
+// Extra force between nearby verlets on top of the hard contact in solve_collisions which only ever pushes apart
+// Everything is measured from contact - the distance where the two balls just touch - so it works for any mix of radii
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PairPotential {
+    #[default]
+    None,
+    // 4 epsilon ((sigma / r)^12 - (sigma / r)^6) with sigma picked so the bottom of the well is right at contact
+    // cutoff is in contacts - 2.2 is the usual 2.5 sigma
+    LennardJones { epsilon: f32, cutoff: f32 },
+    // Pull of strength while touching that fades out band past contact - like the water bridges in wet sand
+    Cohesion { strength: f32, band: f32 },
+}
+
+// Lennard-Jones is capped here so a pair squeezed together in one substep doesn't get shot apart at r^-13
+const MIN_LENNARD_JONES_RATIO: f32 = 0.85;
+
+impl PairPotential {
+    // Center to center distance past which the pair doesn't feel anything
+    pub fn range(&self, contact: f32) -> f32 {
+        match *self {
+            PairPotential::None => 0.0,
+            PairPotential::LennardJones { cutoff, .. } => contact * cutoff,
+            PairPotential::Cohesion { band, .. } => contact + band,
+        }
+    }
+
+    // Force along the line between the centers at dist apart - positive pushes apart and negative pulls together
+    pub fn force(&self, dist: f32, contact: f32) -> f32 {
+        match *self {
+            PairPotential::None => 0.0,
+            PairPotential::LennardJones { epsilon, cutoff } => {
+                let range = contact * cutoff;
+                if dist >= range {
+                    return 0.0;
+                }
+                // Shifted so it's already 0 at the cutoff instead of snapping off there
+                let sigma = contact / 2.0_f32.powf(1.0 / 6.0);
+                let lennard_jones = |r: f32| {
+                    let six = (sigma / r).powi(6);
+                    24.0 * epsilon * (2.0 * six * six - six) / r
+                };
+                lennard_jones(dist.max(contact * MIN_LENNARD_JONES_RATIO)) - lennard_jones(range)
+            }
+            PairPotential::Cohesion { strength, band } => {
+                let gap = dist - contact;
+                if gap >= band {
+                    return 0.0;
+                }
+                -strength * (1.0 - gap.max(0.0) / band)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lennard_jones_fades_to_nothing_at_the_cutoff() {
+        let potential = PairPotential::LennardJones { epsilon: 5.0, cutoff: 2.2 };
+        let contact = 20.0;
+        let range = potential.range(contact);
+        assert_eq!(range, 44.0);
+        assert_eq!(potential.force(range, contact), 0.0);
+        assert_eq!(potential.force(range + 10.0, contact), 0.0);
+        // Continuous going into the cutoff rather than snapping off
+        assert!(potential.force(range - 1e-3, contact).abs() < 1e-4);
+        // Pulls together between the well and the cutoff and pushes apart when squeezed
+        assert!(potential.force(30.0, contact) < 0.0);
+        assert!(potential.force(15.0, contact) > 0.0);
+        // Capped instead of blowing up at r^-13
+        assert_eq!(potential.force(1.0, contact), potential.force(contact * MIN_LENNARD_JONES_RATIO, contact));
+    }
+
+    #[test]
+    fn cohesion_fades_to_nothing_at_the_end_of_the_band() {
+        let potential = PairPotential::Cohesion { strength: 3.0, band: 4.0 };
+        let contact = 20.0;
+        assert_eq!(potential.range(contact), 24.0);
+        assert_eq!(potential.force(18.0, contact), -3.0);
+        assert_eq!(potential.force(22.0, contact), -1.5);
+        assert_eq!(potential.force(24.0, contact), 0.0);
+        assert_eq!(PairPotential::None.force(10.0, contact), 0.0);
+    }
+}
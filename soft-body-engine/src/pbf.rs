@@ -0,0 +1,38 @@
+use super::sph::poly6;
+
+// Position based fluids - instead of pressure forces the density around every fluid verlet is a constraint that gets projected after integrating
+// https://mmacklin.com/pbf_sig_preprint.pdf - stays stiff at big steps where SPH would need a huge stiffness and tiny substeps
+// Meant for 1 or 2 substeps - every correction turns into velocity / dt so lots of tiny substeps make it jittery and puffy
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pbf {
+    pub smoothing_radius: f32,
+    pub rest_density: f32, // Mass per area the fluid settles at - verlets spaced d apart come out around mass / d^2
+    pub iterations: usize, // More is less squishy
+    pub relaxation: f32, // Epsilon in the denominator in units of 1 / smoothing_radius^2 - keeps the step sane when a verlet has almost no neighbors
+    pub tensile_correction: f32, // Artificial pressure that pushes apart close pairs so the surface doesn't clump - around 0.1
+    pub viscosity: f32, // XSPH - how much of the neighbors' average velocity every verlet takes on, around 0.01
+}
+
+impl Pbf {
+    pub fn new(smoothing_radius: f32, rest_density: f32, iterations: usize) -> Self {
+        Pbf {
+            smoothing_radius,
+            rest_density,
+            iterations,
+            relaxation: 1e-4,
+            tensile_correction: 0.1,
+            viscosity: 0.01,
+        }
+    }
+
+    // C = density / rest_density - 1 clamped so it only ever pushes apart like a free surface should
+    pub fn constraint(&self, density: f32) -> f32 {
+        (density / self.rest_density - 1.0).max(0.0)
+    }
+
+    // s_corr from the paper with dq = 0.2 h and n = 4 - always negative so it acts like a little bit of surface tension
+    pub fn tensile_instability(&self, dist_squared: f32) -> f32 {
+        let reference = poly6((0.2 * self.smoothing_radius).powi(2), self.smoothing_radius);
+        -self.tensile_correction * (poly6(dist_squared, self.smoothing_radius) / reference).powi(4)
+    }
+}
@@ -0,0 +1,124 @@
+use glam::Vec2;
+use super::verlet::Verlet;
+use super::shape_match::ShapeMatch;
+
+// A rigid body is just a cluster of verlets that keeps its rest shape exactly
+// The particles still integrate and collide like normal through the grid
+// Afterwards we collect their momentum into one body and put them back into the rigid shape
+#[derive(Clone, Debug)]
+pub struct RigidBody {
+    shape: ShapeMatch, // Used for the rest offsets and the best fit rotation
+    center: Vec2,
+    angle: f32,
+    velocity: Vec2,
+    angular_velocity: f32,
+    mass: f32,
+    inertia: f32,
+}
+
+impl RigidBody {
+    pub fn new(indices: &[usize], verlets: &[Verlet]) -> Self {
+        let shape = ShapeMatch::new(indices, verlets, 1.0);
+        let (center, _) = shape.best_fit(verlets);
+        let (mass, inertia) = Self::mass_properties(&shape, verlets);
+
+        RigidBody {
+            shape,
+            center,
+            angle: 0.0,
+            velocity: Vec2::ZERO,
+            angular_velocity: 0.0,
+            mass,
+            inertia,
+        }
+    }
+
+    fn mass_properties(shape: &ShapeMatch, verlets: &[Verlet]) -> (f32, f32) {
+        let mut mass = 0.0;
+        let mut inertia = 0.0;
+        for (&i, &rest) in shape.get_indices().iter().zip(shape.get_rest_offsets()) {
+            let verlet = &verlets[i];
+            let radius = verlet.get_radius();
+            mass += verlet.get_mass();
+            // Parallel axis theorem - the disc spinning about itself plus it orbiting the center
+            inertia += verlet.get_mass() * (0.5 * radius * radius + rest.length_squared());
+        }
+        (mass, inertia)
+    }
+
+    // Same as ShapeMatch::remap - mass and inertia are worked out again for what's left
+    pub fn remap(&mut self, remap: &[Option<usize>], verlets: &[Verlet]) {
+        self.shape.remap(remap, verlets);
+        (self.mass, self.inertia) = Self::mass_properties(&self.shape, verlets);
+    }
+
+    pub fn get_indices(&self) -> &Vec<usize> {
+        self.shape.get_indices()
+    }
+
+    #[allow(dead_code)]
+    pub fn get_center(&self) -> Vec2 {
+        self.center
+    }
+
+    #[allow(dead_code)]
+    pub fn get_angle(&self) -> f32 {
+        self.angle
+    }
+
+    #[allow(dead_code)]
+    pub fn get_velocity(&self) -> Vec2 {
+        self.velocity
+    }
+
+    #[allow(dead_code)]
+    pub fn get_angular_velocity(&self) -> f32 {
+        self.angular_velocity
+    }
+
+    #[allow(dead_code)]
+    pub fn get_mass(&self) -> f32 {
+        self.mass
+    }
+
+    #[allow(dead_code)]
+    pub fn get_inertia(&self) -> f32 {
+        self.inertia
+    }
+
+    pub fn solve(&mut self, verlets: &mut [Verlet], dt: f32) {
+        let (center, angle) = self.shape.best_fit(verlets);
+
+        // Linear momentum / total mass gives the center of mass velocity
+        let mut momentum = Vec2::ZERO;
+        for &i in self.shape.get_indices() {
+            momentum += verlets[i].get_velocity() * verlets[i].get_mass();
+        }
+        let velocity = momentum / self.mass;
+
+        // L = sum(m * r x v) relative to the center then w = L / I
+        let mut angular_momentum = 0.0;
+        for &i in self.shape.get_indices() {
+            let offset = verlets[i].get_position() - center;
+            let relative_velocity = verlets[i].get_velocity() - velocity;
+            angular_momentum += verlets[i].get_mass() * offset.perp_dot(relative_velocity);
+        }
+        let angular_velocity = angular_momentum / self.inertia;
+
+        // Snap every particle back onto the rigid shape moving with v + w x r
+        let rotation = Vec2::from_angle(angle);
+        for (&i, &rest) in self.shape.get_indices().iter().zip(self.shape.get_rest_offsets()) {
+            if verlets[i].is_static() {
+                continue;
+            }
+            let offset = rotation.rotate(rest);
+            verlets[i].set_position(center + offset);
+            verlets[i].set_velocity(velocity + angular_velocity * offset.perp(), dt);
+        }
+
+        self.center = center;
+        self.angle = angle;
+        self.velocity = velocity;
+        self.angular_velocity = angular_velocity;
+    }
+}
@@ -0,0 +1,358 @@
+use glam::Vec2;
+
+// Signed distance to the nearest wall sampled on a grid stretched over min..max
+// Positive is free space and negative is inside something solid so a circle touches a wall when the distance drops below its radius
+#[derive(Clone, Debug, PartialEq)]
+pub struct SignedDistanceField {
+    width: usize,
+    height: usize,
+    min: Vec2,
+    max: Vec2,
+    distances: Vec<f32>, // Row 0 is the top of the image which is max.y in the solver - walled in by free space for obstacles
+    walled_distances: Vec<f32>, // Same but walled in by solid for containers
+    contour: Vec<Vec<Vec2>>, // Marching squares is too slow to redo every frame just to draw it
+    walled_contour: Vec<Vec<Vec2>>,
+}
+
+// Stands in for infinity so the parabola math doesn't end up with inf - inf
+const FAR: f32 = 1e20;
+
+impl SignedDistanceField {
+    // White (bright) pixels are free space and black (dark) ones are solid
+    // Unlike color_from_image x isn't mirrored since this is meant for drawing levels the way they look
+    pub fn from_image(file_path: &str, min: Vec2, max: Vec2) -> Result<Self, Box<dyn std::error::Error>> {
+        let img = image::open(file_path)?;
+        let luma_img = img.to_luma8();
+        let (width, height) = luma_img.dimensions();
+
+        let solid: Vec<bool> = luma_img.pixels().map(|pixel| pixel[0] < 128).collect();
+        Ok(Self::from_mask(&solid, width as usize, height as usize, min, max)?)
+    }
+
+    // solid is row by row from the top like an image
+    pub fn from_mask(solid: &[bool], width: usize, height: usize, min: Vec2, max: Vec2) -> Result<Self, String> {
+        if width == 0 || height == 0 {
+            return Err::<Self, String>(String::from("Mask needs at least 1 pixel"));
+        }
+        if solid.len() != width * height {
+            return Err::<Self, String>(String::from("Mask size doesn't match width * height"));
+        }
+
+        // A ring of pixels around the outside stands in for everything past the edge of the image
+        // Otherwise the distance only sees what's inside and an all white container is 1e10 from a wall everywhere
+        // Nothing solid is fine for a container since the ring is its wall - ColliderShape::sdf is what turns it down as an obstacle
+        let pixel_size = (max - min) / Vec2::new(width as f32, height as f32);
+        let padded_corner = Vec2::new(min.x - pixel_size.x, max.y + pixel_size.y);
+        let padded = signed_distances(&pad(solid, width, height, false), width + 2, height + 2, pixel_size);
+        let walled = signed_distances(&pad(solid, width, height, true), width + 2, height + 2, pixel_size);
+
+        Ok(SignedDistanceField {
+            width,
+            height,
+            min,
+            max,
+            distances: crop(&padded, width, height),
+            walled_distances: crop(&walled, width, height),
+            contour: marching_squares(&padded, width + 2, height + 2, padded_corner, pixel_size),
+            walled_contour: marching_squares(&walled, width + 2, height + 2, padded_corner, pixel_size),
+        })
+    }
+
+    #[allow(dead_code)]
+    pub fn get_width(&self) -> usize {
+        self.width
+    }
+
+    #[allow(dead_code)]
+    pub fn get_height(&self) -> usize {
+        self.height
+    }
+
+    pub fn aabb(&self) -> (Vec2, Vec2) {
+        (self.min, self.max)
+    }
+
+    // The obstacle outline only comes out empty when no pixel in the mask was solid
+    pub fn has_solid(&self) -> bool {
+        !self.contour.is_empty()
+    }
+
+    fn pixel_size(&self) -> Vec2 {
+        (self.max - self.min) / Vec2::new(self.width as f32, self.height as f32)
+    }
+
+    // Area of all the free pixels
+    #[allow(dead_code)]
+    pub fn free_area(&self) -> f32 {
+        let pixel_size = self.pixel_size();
+        self.walled_distances.iter().filter(|&&distance| distance > 0.0).count() as f32 * pixel_size.x * pixel_size.y
+    }
+
+    // Bilinear between pixel centers
+    // Outside the image counts as solid when it's a container and as free space when it's an obstacle
+    pub fn distance(&self, position: Vec2, outside_solid: bool) -> f32 {
+        let clamped = position.clamp(self.min, self.max);
+        let outside = (position - clamped).length();
+
+        let pixel_size = self.pixel_size();
+        let pixel = Vec2::new(clamped.x - self.min.x, self.max.y - clamped.y) / pixel_size - 0.5;
+        let pixel = pixel.clamp(Vec2::ZERO, Vec2::new(self.width as f32 - 1.0, self.height as f32 - 1.0));
+        let (x0, y0) = (pixel.x.floor() as usize, pixel.y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (fx, fy) = (pixel.x - x0 as f32, pixel.y - y0 as f32);
+
+        let distances = if outside_solid { &self.walled_distances } else { &self.distances };
+        let at = |x: usize, y: usize| distances[y * self.width + x];
+        let top = at(x0, y0) + (at(x1, y0) - at(x0, y0)) * fx;
+        let bottom = at(x0, y1) + (at(x1, y1) - at(x0, y1)) * fx;
+        let distance = top + (bottom - top) * fy;
+
+        if outside_solid {
+            distance - outside
+        } else {
+            distance + outside
+        }
+    }
+
+    // Points towards free space - central differences half a pixel either way
+    pub fn gradient(&self, position: Vec2, outside_solid: bool) -> Vec2 {
+        let step = 0.5 * self.pixel_size();
+        let dx = self.distance(position + Vec2::new(step.x, 0.0), outside_solid) - self.distance(position - Vec2::new(step.x, 0.0), outside_solid);
+        let dy = self.distance(position + Vec2::new(0.0, step.y), outside_solid) - self.distance(position - Vec2::new(0.0, step.y), outside_solid);
+        Vec2::new(dx / (2.0 * step.x), dy / (2.0 * step.y))
+    }
+
+    // If a circle at position is closer than its radius to something solid gives back where it should be and the normal pointing away from the solid
+    pub fn contact(&self, position: Vec2, radius: f32, outside_solid: bool) -> Option<(Vec2, Vec2)> {
+        let distance = self.distance(position, outside_solid);
+        if distance >= radius {
+            return None;
+        }
+        // Flat spots in the middle of a blob have no gradient so there's nowhere sensible to push
+        let normal = self.gradient(position, outside_solid).try_normalize()?;
+        Some((position + normal * (radius - distance), normal))
+    }
+
+    // Sphere tracing - step forward by the distance to the nearest wall which can never overshoot it
+    pub fn time_of_impact(&self, start: Vec2, motion: Vec2, radius: f32, outside_solid: bool) -> Option<(f32, Vec2)> {
+        let length = motion.length();
+        if length == 0.0 || self.distance(start, outside_solid) < radius {
+            return None;
+        }
+
+        let tolerance = 0.01 * self.pixel_size().min_element();
+        let mut t = 0.0;
+        for _ in 0..32 {
+            let point = start + motion * t;
+            let gap = self.distance(point, outside_solid) - radius;
+            if gap < tolerance {
+                return Some((t, self.gradient(point, outside_solid).try_normalize().unwrap_or(-motion / length)));
+            }
+            t += gap / length;
+            if t >= 1.0 {
+                return None;
+            }
+        }
+        None
+    }
+
+    // Zero contour worked out when the field was built - every piece comes back as its own 2 point line
+    // The container one runs along the edge of the image wherever there's free space against it
+    pub fn contour(&self, outside_solid: bool) -> &[Vec<Vec2>] {
+        if outside_solid {
+            &self.walled_contour
+        } else {
+            &self.contour
+        }
+    }
+}
+
+// Mask with a 1 pixel border of border_solid all the way around
+fn pad(solid: &[bool], width: usize, height: usize, border_solid: bool) -> Vec<bool> {
+    let mut padded = vec![border_solid; (width + 2) * (height + 2)];
+    for y in 0..height {
+        padded[(y + 1) * (width + 2) + 1..(y + 1) * (width + 2) + 1 + width].copy_from_slice(&solid[y * width..(y + 1) * width]);
+    }
+    padded
+}
+
+// Takes the border back off
+fn crop(padded: &[f32], width: usize, height: usize) -> Vec<f32> {
+    (0..height)
+        .flat_map(|y| padded[(y + 1) * (width + 2) + 1..(y + 1) * (width + 2) + 1 + width].iter().copied())
+        .collect()
+}
+
+fn signed_distances(solid: &[bool], width: usize, height: usize, pixel_size: Vec2) -> Vec<f32> {
+    let to_solid = distance_transform(solid, width, height, pixel_size);
+    let free: Vec<bool> = solid.iter().map(|&is_solid| !is_solid).collect();
+    let to_free = distance_transform(&free, width, height, pixel_size);
+
+    // The wall is on the pixel border so half a pixel comes off the center to center distance
+    let half_pixel = 0.5 * pixel_size.min_element();
+    solid.iter().enumerate()
+        .map(|(i, &is_solid)| if is_solid {
+            -(to_free[i].sqrt() - half_pixel)
+        } else {
+            to_solid[i].sqrt() - half_pixel
+        })
+        .collect()
+}
+
+// Marching squares over the pixel centers - corner is the top left of pixel 0
+fn marching_squares(distances: &[f32], width: usize, height: usize, corner: Vec2, pixel_size: Vec2) -> Vec<Vec<Vec2>> {
+    let center = |x: usize, y: usize| Vec2::new(
+        corner.x + (x as f32 + 0.5) * pixel_size.x,
+        corner.y - (y as f32 + 0.5) * pixel_size.y,
+    );
+    let at = |x: usize, y: usize| distances[y * width + x];
+
+    let mut lines = vec![];
+    for y in 0..height.saturating_sub(1) {
+        for x in 0..width.saturating_sub(1) {
+            // Going around the square so a saddle pairs up its crossings in order
+            let corners = [(x, y), (x + 1, y), (x + 1, y + 1), (x, y + 1)];
+            let mut crossings = vec![];
+            for i in 0..4 {
+                let (a, b) = (corners[i], corners[(i + 1) % 4]);
+                let (distance_a, distance_b) = (at(a.0, a.1), at(b.0, b.1));
+                if (distance_a > 0.0) != (distance_b > 0.0) {
+                    let t = distance_a / (distance_a - distance_b);
+                    crossings.push(center(a.0, a.1).lerp(center(b.0, b.1), t));
+                }
+            }
+            for pair in crossings.chunks_exact(2) {
+                lines.push(pair.to_vec());
+            }
+        }
+    }
+    lines
+}
+
+// Squared distance from every pixel center to the nearest feature pixel's center
+// https://cs.brown.edu/people/pfelzens/papers/dt-final.pdf - exact and linear since it splits into a pass over rows then columns
+fn distance_transform(feature: &[bool], width: usize, height: usize, pixel_size: Vec2) -> Vec<f32> {
+    let mut distances: Vec<f32> = feature.iter().map(|&is_feature| if is_feature { 0.0 } else { FAR }).collect();
+
+    let longest = width.max(height);
+    let mut line = vec![0.0; longest];
+    let mut result = vec![0.0; longest];
+    let mut parabolas = vec![0; longest];
+    let mut boundaries = vec![0.0; longest + 1];
+
+    for y in 0..height {
+        line[..width].copy_from_slice(&distances[y * width..(y + 1) * width]);
+        distance_transform_1d(&line[..width], pixel_size.x, &mut result, &mut parabolas, &mut boundaries);
+        distances[y * width..(y + 1) * width].copy_from_slice(&result[..width]);
+    }
+    for x in 0..width {
+        for y in 0..height {
+            line[y] = distances[y * width + x];
+        }
+        distance_transform_1d(&line[..height], pixel_size.y, &mut result, &mut parabolas, &mut boundaries);
+        for y in 0..height {
+            distances[y * width + x] = result[y];
+        }
+    }
+
+    distances
+}
+
+// Lower envelope of the parabolas (q - p)^2 + f(p) with p spread spacing apart
+fn distance_transform_1d(f: &[f32], spacing: f32, result: &mut [f32], parabolas: &mut [usize], boundaries: &mut [f32]) {
+    let n = f.len();
+    let position = |i: usize| i as f32 * spacing;
+
+    let mut k = 0;
+    parabolas[0] = 0;
+    boundaries[0] = f32::NEG_INFINITY;
+    boundaries[1] = f32::INFINITY;
+    for q in 1..n {
+        // Where parabola q starts beating the one on top of the envelope - drop that one if it never gets a turn
+        let mut intersection;
+        loop {
+            let p = parabolas[k];
+            intersection = ((f[q] + position(q) * position(q)) - (f[p] + position(p) * position(p))) / (2.0 * (position(q) - position(p)));
+            if intersection <= boundaries[k] && k > 0 {
+                k -= 1;
+            } else {
+                break;
+            }
+        }
+        k += 1;
+        parabolas[k] = q;
+        boundaries[k] = intersection;
+        boundaries[k + 1] = f32::INFINITY;
+    }
+
+    k = 0;
+    for (q, value) in result.iter_mut().enumerate().take(n) {
+        while boundaries[k + 1] < position(q) {
+            k += 1;
+        }
+        let offset = position(q) - position(parabolas[k]);
+        *value = offset * offset + f[parabolas[k]];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use glam::vec2;
+    use super::*;
+    use super::super::collider::ColliderShape;
+
+    #[test]
+    fn distance_transform_of_a_known_mask() {
+        // Feature in the middle of a 3 by 3 with pixels twice as wide as they are tall
+        let mut feature = vec![false; 9];
+        feature[4] = true;
+        let distances = distance_transform(&feature, 3, 3, vec2(2.0, 1.0));
+        assert_eq!(distances, vec![
+            5.0, 1.0, 5.0,
+            4.0, 0.0, 4.0,
+            5.0, 1.0, 5.0,
+        ]);
+
+        // Nearest of two features along a row
+        let feature = [true, false, false, false, false, true];
+        let distances = distance_transform(&feature, 6, 1, Vec2::ONE);
+        assert_eq!(distances, vec![0.0, 1.0, 4.0, 4.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn mask_with_nothing_solid_is_only_a_container() {
+        let field = SignedDistanceField::from_mask(&[false; 100], 10, 10, vec2(0.0, 0.0), vec2(10.0, 10.0)).unwrap();
+        assert!(!field.has_solid());
+        // The edge of the image is still a wall to be inside of
+        assert!((field.distance(vec2(0.5, 4.5), true) - 0.5).abs() < 1e-4);
+        assert!(field.contact(vec2(0.5, 4.5), 1.0, true).is_some());
+        assert!(!field.contour(true).is_empty());
+
+        // As an obstacle there'd be nothing to hit
+        assert!(ColliderShape::sdf(Rc::new(field)).is_err());
+        let mut solid = vec![false; 100];
+        solid[55] = true;
+        let field = SignedDistanceField::from_mask(&solid, 10, 10, vec2(0.0, 0.0), vec2(10.0, 10.0)).unwrap();
+        assert!(ColliderShape::sdf(Rc::new(field)).is_ok());
+    }
+
+    #[test]
+    fn image_edge_is_a_wall_only_for_containers() {
+        // 10 by 10 pixels of size 1 with one solid pixel in the middle
+        let mut solid = vec![false; 100];
+        solid[5 * 10 + 5] = true;
+        let field = SignedDistanceField::from_mask(&solid, 10, 10, vec2(0.0, 0.0), vec2(10.0, 10.0)).unwrap();
+
+        // Half a pixel in from the left edge
+        let near_edge = vec2(0.5, 4.5);
+        assert!((field.distance(near_edge, true) - 0.5).abs() < 1e-4);
+        assert!((field.distance(near_edge, false) - 4.5).abs() < 1e-4);
+        // Past the edge is solid for a container and still free for an obstacle
+        assert!(field.distance(vec2(-1.0, 4.5), true) < 0.0);
+        assert!(field.distance(vec2(-1.0, 4.5), false) > 0.0);
+        // The container outline goes around the edge as well as the solid pixel
+        assert!(field.contour(true).len() > field.contour(false).len());
+    }
+}
@@ -0,0 +1,102 @@
+use glam::Vec2;
+use super::verlet::Verlet;
+
+// Müller style shape matching - https://matthias-research.github.io/pages/publications/MeshlessDeformations_SIG05.pdf
+// We remember the rest shape and every step find the rotation + translation that best fits the current particles
+// Then we just pull each particle towards where it would be if the body was rigid
+#[derive(Clone, Debug)]
+pub struct ShapeMatch {
+    indices: Vec<usize>,
+    rest_offsets: Vec<Vec2>, // Rest positions relative to the rest center of mass
+    stiffness: f32,
+}
+
+impl ShapeMatch {
+    pub fn new(indices: &[usize], verlets: &[Verlet], stiffness: f32) -> Self {
+        let center = Self::center_of_mass(indices, verlets);
+        let rest_offsets = indices.iter()
+            .map(|&i| verlets[i].get_position() - center)
+            .collect();
+
+        ShapeMatch {
+            indices: indices.to_vec(),
+            rest_offsets,
+            stiffness: stiffness.clamp(0.0, 1.0),
+        }
+    }
+
+    pub fn get_indices(&self) -> &Vec<usize> {
+        &self.indices
+    }
+
+    pub fn get_rest_offsets(&self) -> &Vec<Vec2> {
+        &self.rest_offsets
+    }
+
+    #[allow(dead_code)]
+    pub fn get_stiffness(&self) -> f32 {
+        self.stiffness
+    }
+
+    #[allow(dead_code)]
+    pub fn set_stiffness(&mut self, stiffness: f32) {
+        self.stiffness = stiffness.clamp(0.0, 1.0);
+    }
+
+    // remap is the new index for every old one or None if that verlet is gone - verlets is already the new list
+    // The rest shape loses the removed points and gets re-centered on what's left
+    pub fn remap(&mut self, remap: &[Option<usize>], verlets: &[Verlet]) {
+        let (indices, rest_offsets): (Vec<usize>, Vec<Vec2>) = self.indices.iter().zip(&self.rest_offsets)
+            .filter_map(|(&i, &rest)| remap[i].map(|new_index| (new_index, rest)))
+            .unzip();
+
+        let total_mass: f32 = indices.iter().map(|&i| verlets[i].get_mass()).sum();
+        let rest_center = indices.iter().zip(&rest_offsets).map(|(&i, &rest)| rest * verlets[i].get_mass()).sum::<Vec2>() / total_mass;
+
+        self.rest_offsets = rest_offsets.iter().map(|&rest| rest - rest_center).collect();
+        self.indices = indices;
+    }
+
+    fn center_of_mass(indices: &[usize], verlets: &[Verlet]) -> Vec2 {
+        let mut total_mass = 0.0;
+        let mut center = Vec2::ZERO;
+        for &i in indices {
+            let mass = verlets[i].get_mass();
+            center += verlets[i].get_position() * mass;
+            total_mass += mass;
+        }
+        center / total_mass
+    }
+
+    // Best fit rotation angle of the rest shape onto the current shape
+    // In 2D the rotation that maximizes sum(m * p . R q) is just atan2 of the summed cross and dot products
+    pub fn best_fit(&self, verlets: &[Verlet]) -> (Vec2, f32) {
+        let center = Self::center_of_mass(&self.indices, verlets);
+
+        let mut cross = 0.0;
+        let mut dot = 0.0;
+        for (&i, &rest) in self.indices.iter().zip(&self.rest_offsets) {
+            let mass = verlets[i].get_mass();
+            let current = verlets[i].get_position() - center;
+            cross += mass * rest.perp_dot(current);
+            dot += mass * rest.dot(current);
+        }
+
+        (center, cross.atan2(dot))
+    }
+
+    pub fn solve(&self, verlets: &mut [Verlet]) {
+        let (center, angle) = self.best_fit(verlets);
+        let rotation = Vec2::from_angle(angle);
+
+        for (&i, &rest) in self.indices.iter().zip(&self.rest_offsets) {
+            if verlets[i].is_static() {
+                continue;
+            }
+            let goal = center + rotation.rotate(rest);
+            let position = verlets[i].get_position();
+            // Moving only the position means verlet picks up the correction as velocity too
+            verlets[i].set_position(position + (goal - position) * self.stiffness);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::ops::Range;
+
+// Settings shared by all the generated soft bodies
+#[derive(Clone, Debug)]
+pub struct BodyOptions {
+    pub spacing: f32,       // Rest distance between neighbouring particles
+    pub radius: f32,        // Radius of every particle in the body
+    pub shear_springs: bool, // Diagonal springs so the grid can't collapse into a rhombus
+    pub bend_springs: bool,  // Springs that skip a particle so the body resists folding
+    pub pinned: Vec<usize>, // Local particle indices that get anchored in place
+    pub collision_group: u32, // Non zero stops the body colliding with itself
+}
+
+impl Default for BodyOptions {
+    fn default() -> Self {
+        BodyOptions {
+            spacing: 20.0,
+            radius: 5.0,
+            shear_springs: true,
+            bend_springs: false,
+            pinned: vec![],
+            collision_group: 0,
+        }
+    }
+}
+
+// Where a generated body lives inside the solver
+// Particles and constraints are always added in one block so ranges are enough
+#[derive(Clone, Debug)]
+pub struct BodyHandle {
+    #[allow(dead_code)]
+    verlets: Range<usize>,
+    #[allow(dead_code)]
+    constraints: Range<usize>,
+}
+
+impl BodyHandle {
+    pub fn new(verlets: Range<usize>, constraints: Range<usize>) -> Self {
+        BodyHandle {
+            verlets,
+            constraints,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn get_verlets(&self) -> Range<usize> {
+        self.verlets.clone()
+    }
+
+    #[allow(dead_code)]
+    pub fn get_constraints(&self) -> Range<usize> {
+        self.constraints.clone()
+    }
+
+    #[allow(dead_code)]
+    pub fn get_verlet(&self, local_index: usize) -> Option<usize> {
+        let index = self.verlets.start + local_index;
+        if index < self.verlets.end {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.verlets.len()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.verlets.is_empty()
+    }
+}
@@ -1,141 +1,1154 @@
 use std::vec;
+use std::collections::HashSet;
 
 use glam::{Vec2, Vec4};
 use super::verlet::Verlet;
+use super::integrator::Integrator;
+use super::soft_body::{BodyHandle, BodyOptions};
+use super::shape_match::ShapeMatch;
+use super::rigid_body::RigidBody;
+use super::boundary::{Boundary, BoundaryTransform, time_of_impact};
+use super::collider::{Collider, bounce};
+#[cfg(test)]
+use super::collider::ColliderShape;
+use super::force_field::ForceField;
+use super::barnes_hut::QuadTree;
+use super::pair_potential::PairPotential;
+use super::sph::{Sph, poly6, spiky_gradient, viscosity_laplacian};
+use super::pbf::Pbf;
+use super::thermal::Thermal;
+use super::merging::MergeRules;
+
+// How close past touching still counts as touching for things that rest on each other - a fraction of the radius
+// A sleeping stack sits exactly touching so without it nothing on top would hold up or pass heat along
+const CONTACT_SLOP: f32 = 0.1;
+// Only contacts at least this far below the middle along gravity hold a verlet up - a row of balls touching side by side doesn't
+const SUPPORT_MIN_COS: f32 = 0.1;
 
 pub struct Solver {
     verlets: Vec<Verlet>,
     gravity: Vec2,
-    constraint_radius: f32,
+    boundary: Boundary,
+    boundary_transform: BoundaryTransform,
+    wall_friction: f32,
     subdivision: usize,
     cell_size: f32,
-    grid_size: usize,
+    grid_origin: Vec2,
+    grid_cell_size: Vec2,
+    grid_width: usize,
+    grid_height: usize,
     grid: Vec<Vec<usize>>,
+    moving_cells: Vec<bool>, // Cells with something that isn't anchored or asleep - pairs between cells without one can't collide
     color_frames: Vec<Vec4>,
     current_frame: usize,
     constraints: Vec<(usize, usize, f32)>,
     contraint_spring_constant: f32,
+    shape_matches: Vec<ShapeMatch>,
+    rigid_bodies: Vec<RigidBody>,
+    constrained_pairs: HashSet<(usize, usize)>,
+    skip_constrained_collisions: bool,
+    integrator: Integrator,
+    adaptive_subdivision: bool,
+    max_subdivision: usize,
+    max_displacement_ratio: f32,
+    last_subdivision: usize,
+    sleeping_enabled: bool,
+    sleep_speed: f32,
+    sleep_time: f32,
+    colliders: Vec<Collider>,
+    collider_grid: Vec<Vec<usize>>, // Fixed in world space over all the colliders so a moving boundary never touches it
+    collider_grid_origin: Vec2,
+    collider_grid_width: usize,
+    collider_grid_height: usize,
+    force_fields: Vec<Box<dyn ForceField>>,
+    n_body_gravity: bool,
+    gravitational_constant: f32,
+    opening_angle: f32,
+    gravity_softening: f32,
+    coulomb_constant: f32,
+    coulomb_cutoff: f32,
+    magnetic_field: f32,
+    pair_potential: PairPotential,
+    sph: Option<Sph>,
+    pbf: Option<Pbf>,
+    thermal: Option<Thermal>,
+    merge_rules: Option<MergeRules>,
+    index_remap: Vec<Option<usize>>, // Every removal since take_index_remap was last called - empty when nothing moved
+    fluid_densities: Vec<f32>,
 }
 
 
 impl Solver {
+    // Starts out in a circle of constraint_radius around the origin - set_boundary swaps it for any other shape
     pub fn new(verlets: &[Verlet], gravity: Vec2, constraint_radius: f32, subdivision: usize, cell_size: f32, contraint_spring_constant: f32) -> Self {
-        let grid_size = (constraint_radius * 2.0 / cell_size) as usize; 
-        Solver {
-            verlets: verlets.iter().cloned().collect(),
+        let mut solver = Solver {
+            verlets: verlets.to_vec(),
             gravity,
-            constraint_radius,
+            boundary: Boundary::Circle { center: Vec2::ZERO, radius: constraint_radius },
+            boundary_transform: BoundaryTransform::default(),
+            wall_friction: 0.0,
             subdivision,
             cell_size,
-            grid_size,
-            grid: vec![vec![]; grid_size * grid_size],
+            grid_origin: Vec2::ZERO,
+            grid_cell_size: Vec2::splat(cell_size),
+            grid_width: 0,
+            grid_height: 0,
+            grid: vec![],
+            moving_cells: vec![],
             color_frames: Vec::new(),
             current_frame: 0,
             constraints: vec![],
-            contraint_spring_constant
+            contraint_spring_constant,
+            shape_matches: vec![],
+            rigid_bodies: vec![],
+            constrained_pairs: HashSet::new(),
+            skip_constrained_collisions: false,
+            integrator: Integrator::default(),
+            adaptive_subdivision: false,
+            max_subdivision: subdivision,
+            max_displacement_ratio: 0.5,
+            last_subdivision: subdivision,
+            sleeping_enabled: false,
+            sleep_speed: 5.0,
+            sleep_time: 0.5,
+            colliders: vec![],
+            collider_grid: vec![],
+            collider_grid_origin: Vec2::ZERO,
+            collider_grid_width: 0,
+            collider_grid_height: 0,
+            force_fields: vec![],
+            n_body_gravity: false,
+            gravitational_constant: 1.0,
+            opening_angle: 0.5,
+            gravity_softening: 1.0,
+            coulomb_constant: 0.0,
+            coulomb_cutoff: 0.0,
+            magnetic_field: 0.0,
+            pair_potential: PairPotential::None,
+            sph: None,
+            pbf: None,
+            thermal: None,
+            merge_rules: None,
+            index_remap: vec![],
+            fluid_densities: vec![],
+        };
+        solver.resize_grid();
+        solver
+    }
+
+    pub fn get_boundary(&self) -> &Boundary {
+        &self.boundary
+    }
+    // Anything asleep could have been resting on the old walls
+    pub fn set_boundary(&mut self, boundary: Boundary) {
+        self.boundary = boundary;
+        self.resize_grid();
+        self.wake_all();
+    }
+
+    // Moves and spins the boundary - the shape given to set_boundary is in this transform's frame
+    pub fn get_boundary_transform(&self) -> &BoundaryTransform {
+        &self.boundary_transform
+    }
+    #[allow(dead_code)]
+    pub fn set_boundary_transform(&mut self, boundary_transform: BoundaryTransform) {
+        self.boundary_transform = boundary_transform;
+        self.resize_grid();
+        self.wake_all();
+    }
+    // Handy for shaking since only the velocity changes every frame
+    // Moving walls can pull the floor out from under anything so it all wakes up
+    pub fn set_boundary_velocity(&mut self, velocity: Vec2, angular_velocity: f32) {
+        self.boundary_transform.velocity = velocity;
+        self.boundary_transform.angular_velocity = angular_velocity;
+        self.resize_grid();
+        if self.boundary_transform.is_moving() {
+            self.wake_all();
         }
     }
+    // Boundary outline in world space for drawing
+    pub fn get_boundary_outline(&self) -> Vec<Vec<Vec2>> {
+        self.boundary.outline().iter()
+            .map(|outline| outline.iter().map(|&point| self.boundary_transform.to_world(point)).collect())
+            .collect()
+    }
+
+    // Coulomb friction between the verlets and the container walls - a rotating drum needs some to drag anything along
+    #[allow(dead_code)]
+    pub fn get_wall_friction(&self) -> f32 {
+        self.wall_friction
+    }
+    pub fn set_wall_friction(&mut self, wall_friction: f32) {
+        self.wall_friction = wall_friction;
+    }
+
+    // The grid covers the boundary's bounding box so a long thin box doesn't pay for a square grid
+    // Gets called every substep while the boundary moves so it only rebuilds when something actually changed
+    fn resize_grid(&mut self) {
+        let (min, max) = self.boundary.world_aabb(&self.boundary_transform);
+        let size = max - min;
+        // Wrapping needs whole cells across so they get stretched a bit instead of leaving a thin one at the edge
+        let (grid_width, grid_height) = if self.boundary.is_periodic() {
+            (((size.x / self.cell_size).floor() as usize).max(1), ((size.y / self.cell_size).floor() as usize).max(1))
+        } else {
+            (((size.x / self.cell_size).ceil() as usize).max(1), ((size.y / self.cell_size).ceil() as usize).max(1))
+        };
+        self.grid_cell_size = if self.boundary.is_periodic() {
+            size / Vec2::new(grid_width as f32, grid_height as f32)
+        } else {
+            Vec2::splat(self.cell_size)
+        };
+        if min == self.grid_origin && grid_width == self.grid_width && grid_height == self.grid_height && !self.grid.is_empty() {
+            return;
+        }
+
+        self.grid_origin = min;
+        if grid_width != self.grid_width || grid_height != self.grid_height {
+            self.grid_width = grid_width;
+            self.grid_height = grid_height;
+            self.grid = vec![vec![]; grid_width * grid_height];
+        }
+    }
+
+    // Sized to fit every collider - only needs doing again when one lands outside it or goes away
+    fn rebuild_collider_grid(&mut self) {
+        let (min, max) = self.colliders.iter()
+            .map(|collider| collider.get_shape().aabb())
+            .fold((Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)), |(min, max), (collider_min, collider_max)| {
+                (min.min(collider_min), max.max(collider_max))
+            });
+        if self.colliders.is_empty() {
+            self.collider_grid = vec![];
+            self.collider_grid_width = 0;
+            self.collider_grid_height = 0;
+            return;
+        }
+
+        let size = ((max - min) / self.cell_size).floor() + 1.0;
+        self.collider_grid_origin = min;
+        self.collider_grid_width = size.x as usize;
+        self.collider_grid_height = size.y as usize;
+        self.collider_grid = vec![vec![]; self.collider_grid_width * self.collider_grid_height];
+        for index in 0..self.colliders.len() {
+            self.insert_collider(index);
+        }
+    }
+
+    // Static colliders get their own grid since they never move - filled once instead of every substep
+    pub fn add_collider(&mut self, collider: Collider) -> usize {
+        let (min, max) = collider.get_shape().aabb();
+        self.colliders.push(collider);
+        let grid_max = self.collider_grid_origin + Vec2::new(self.collider_grid_width as f32, self.collider_grid_height as f32) * self.cell_size;
+        if min.cmpge(self.collider_grid_origin).all() && max.cmplt(grid_max).all() {
+            self.insert_collider(self.colliders.len() - 1);
+        } else {
+            self.rebuild_collider_grid();
+        }
+        self.colliders.len() - 1
+    }
+    pub fn get_colliders(&self) -> &Vec<Collider> {
+        &self.colliders
+    }
+    // Wakes whatever was resting on it - the colliders after it move down one index
+    #[allow(dead_code)]
+    pub fn remove_collider(&mut self, index: usize) -> Result<Collider, String> {
+        if index >= self.colliders.len() {
+            return Err::<Collider, String>(String::from("Index out of bounds"));
+        }
+
+        let (min, max) = self.colliders[index].get_shape().aabb();
+        for verlet in &mut self.verlets {
+            let reach = verlet.get_radius() * (1.0 + CONTACT_SLOP);
+            let position = verlet.get_position();
+            if verlet.is_sleeping() && position.cmpge(min - reach).all() && position.cmple(max + reach).all() {
+                verlet.set_sleeping(false);
+            }
+        }
+
+        let collider = self.colliders.remove(index);
+        self.rebuild_collider_grid();
+        Ok(collider)
+    }
+
+    fn insert_collider(&mut self, index: usize) {
+        let (min, max) = self.colliders[index].get_shape().aabb();
+        let Some((min_cell, max_cell)) = self.collider_cells(min, max) else {
+            return;
+        };
+        for y in min_cell.1..=max_cell.1 {
+            for x in min_cell.0..=max_cell.0 {
+                self.collider_grid[y * self.collider_grid_width + x].push(index);
+            }
+        }
+    }
+
+    // Unclamped collider grid cell - can be negative or past the end
+    fn collider_cell(&self, position: Vec2) -> (isize, isize) {
+        let cell = ((position - self.collider_grid_origin) / self.cell_size).floor();
+        (cell.x as isize, cell.y as isize)
+    }
+
+    // First and last collider grid cells a box overlaps or None when it misses the grid completely
+    fn collider_cells(&self, min: Vec2, max: Vec2) -> Option<((usize, usize), (usize, usize))> {
+        // No colliders leaves a 0 by 0 grid that every box would otherwise land in
+        if self.collider_grid.is_empty() {
+            return None;
+        }
+        let (width, height) = (self.collider_grid_width as isize, self.collider_grid_height as isize);
+        let (min_x, min_y) = self.collider_cell(min);
+        let (max_x, max_y) = self.collider_cell(max);
+        if max_x < 0 || max_y < 0 || min_x >= width || min_y >= height {
+            return None;
+        }
+        Some((
+            (min_x.max(0) as usize, min_y.max(0) as usize),
+            (max_x.min(width - 1) as usize, max_y.min(height - 1) as usize),
+        ))
+    }
+
+    // Anything outside the grid gets put in the closest edge cell so it still collides
+    fn cell_of(&self, position: Vec2) -> (usize, usize) {
+        let cell = ((position - self.grid_origin) / self.grid_cell_size).floor();
+        (
+            (cell.x.max(0.0) as usize).min(self.grid_width - 1),
+            (cell.y.max(0.0) as usize).min(self.grid_height - 1),
+        )
+    }
 
     pub fn update(&mut self, dt: f32) {
-        let sub_dt = dt / self.subdivision as f32;
-        for _ in 0..self.subdivision {
-            for verlet in &mut self.verlets {
-                verlet.add_acceleration(self.gravity);
+        let subdivision = if self.adaptive_subdivision {
+            self.pick_subdivision(dt)
+        } else {
+            self.subdivision
+        };
+        self.update_with_subdivision(dt, subdivision);
+    }
+
+    // CFL like - the fastest verlet shouldn't move more than max_displacement_ratio of its radius in one substep
+    // Otherwise it can tunnel straight through another ball
+    fn pick_subdivision(&self, dt: f32) -> usize {
+        let gravity = self.gravity.length();
+
+        let mut max_ratio: f32 = 0.0;
+        for verlet in &self.verlets {
+            if verlet.is_static() {
+                continue;
+            }
+            let displacement = verlet.get_velocity().length() * dt + 0.5 * gravity * dt * dt;
+            max_ratio = max_ratio.max(displacement / verlet.get_radius());
+        }
+
+        let subdivision = (max_ratio / self.max_displacement_ratio).ceil() as usize;
+        subdivision.clamp(1, self.max_subdivision)
+    }
+
+    pub fn set_adaptive_subdivision(&mut self, adaptive: bool, max_subdivision: usize, max_displacement_ratio: f32) {
+        self.adaptive_subdivision = adaptive;
+        self.max_subdivision = max_subdivision.max(1);
+        self.max_displacement_ratio = max_displacement_ratio;
+    }
+    pub fn is_adaptive_subdivision(&self) -> bool {
+        self.adaptive_subdivision
+    }
+    // How many substeps the last update actually ran
+    pub fn get_last_subdivision(&self) -> usize {
+        self.last_subdivision
+    }
+
+    // Verlet is time corrected so the substep count can change every tick without adding energy
+    pub fn update_with_subdivision(&mut self, dt: f32, subdivision: usize) {
+        let subdivision = subdivision.max(1);
+        self.last_subdivision = subdivision;
+        let sub_dt = dt / subdivision as f32;
+        for _ in 0..subdivision {
+            if self.boundary_transform.is_moving() {
+                self.boundary_transform.advance(sub_dt);
+                self.resize_grid();
             }
 
+            self.apply_periodic_wrap();
             self.apply_wall_constraints(sub_dt);
+            self.apply_wall_heat(sub_dt);
+            self.solve_colliders(sub_dt);
 
             self.solve_contraints();
 
             let collisions: Vec<(usize, usize)> = self.find_collisions_space_partitioning();
+            let disturbed = if self.sleeping_enabled { self.sleepers_near_moving(&collisions) } else { vec![] };
+            self.solve_heat(&collisions, sub_dt);
             self.solve_collisions(collisions, sub_dt);
+            self.solve_charges();
+            self.solve_pair_potential();
+            self.solve_sph();
+
+            self.integrate(sub_dt);
+            self.solve_continuous_collisions(sub_dt);
+            self.solve_pbf(sub_dt);
 
+            self.solve_shape_matches();
+            self.solve_rigid_bodies(sub_dt);
 
-            for verlet in &mut self.verlets {
-                verlet.update_position(sub_dt);
+            if self.sleeping_enabled {
+                self.update_sleeping(sub_dt, &disturbed);
             }
         }
     }
     
+    // Gravity and the force fields go in as one field so RK4 can sample it at its in between states
+    fn integrate(&mut self, dt: f32) {
+        let gravity = self.gravity;
+        for force_field in &mut self.force_fields {
+            force_field.update(dt);
+        }
+
+        // Built once from where everything starts the substep - RK4's in between states only move the verlet asking
+        let tree = self.n_body_gravity.then(|| {
+            let bodies: Vec<(Vec2, f32)> = self.verlets.iter().map(|verlet| (verlet.get_position(), verlet.get_mass())).collect();
+            QuadTree::new(&bodies)
+        });
+        let (gravitational_constant, opening_angle, gravity_softening) = (self.gravitational_constant, self.opening_angle, self.gravity_softening);
+
+        let magnetic_field = self.magnetic_field;
+
+        for (i, verlet) in self.verlets.iter_mut().enumerate() {
+            let mass = verlet.get_mass();
+            let charge = verlet.get_charge();
+            let force_fields = &self.force_fields;
+            let tree = &tree;
+            let field = |position: Vec2, velocity: Vec2| {
+                let mut acceleration = force_fields.iter().fold(gravity, |acceleration, force_field| acceleration + force_field.acceleration(position, velocity, mass));
+                if let Some(tree) = tree {
+                    acceleration += tree.acceleration(position, Some(i), gravitational_constant, opening_angle, gravity_softening);
+                }
+                // Lorentz force q v x B with B pointing out of the screen - bends paths into circles without doing any work
+                if charge != 0.0 && magnetic_field != 0.0 {
+                    acceleration += Vec2::new(velocity.y, -velocity.x) * charge * magnetic_field / mass;
+                }
+                acceleration
+            };
+            verlet.integrate(dt, self.integrator, &field);
+        }
+    }
+
+    // Every verlet pulls on every other one with G * m / r^2 using get_mass - on top of the constant gravity
+    // opening_angle trades accuracy for speed (0 is exact) and softening keeps close passes from slingshotting things away
+    pub fn set_n_body_gravity(&mut self, n_body_gravity: bool, gravitational_constant: f32, opening_angle: f32, gravity_softening: f32) {
+        self.n_body_gravity = n_body_gravity;
+        self.gravitational_constant = gravitational_constant;
+        self.opening_angle = opening_angle;
+        self.gravity_softening = gravity_softening;
+    }
+    pub fn is_n_body_gravity(&self) -> bool {
+        self.n_body_gravity
+    }
+
+    // k * q1 * q2 / r^2 between every pair of charged verlets closer than cutoff - constant 0 turns it off
+    // Shifted so the force is already 0 at the cutoff instead of snapping off there and kicking things
+    pub fn set_coulomb(&mut self, coulomb_constant: f32, coulomb_cutoff: f32) {
+        self.coulomb_constant = coulomb_constant;
+        self.coulomb_cutoff = coulomb_cutoff;
+    }
+    #[allow(dead_code)]
+    pub fn get_coulomb_constant(&self) -> f32 {
+        self.coulomb_constant
+    }
+    #[allow(dead_code)]
+    pub fn get_coulomb_cutoff(&self) -> f32 {
+        self.coulomb_cutoff
+    }
+
+    // Uniform field pointing out of the screen - positive charges circle clockwise in a positive field
+    pub fn set_magnetic_field(&mut self, magnetic_field: f32) {
+        self.magnetic_field = magnetic_field;
+    }
+    pub fn get_magnetic_field(&self) -> f32 {
+        self.magnetic_field
+    }
+
+    fn solve_charges(&mut self) {
+        if self.coulomb_constant == 0.0 || self.coulomb_cutoff <= 0.0 {
+            return;
+        }
+
+        let cutoff_squared = self.coulomb_cutoff * self.coulomb_cutoff;
+        for (i, j) in self.find_pairs_within(self.coulomb_cutoff, |verlet| verlet.get_charge() != 0.0) {
+            let dist_vec = self.displacement(self.verlets[i].get_position(), self.verlets[j].get_position());
+
+            let (left, right) = self.verlets.split_at_mut(j);
+            let verlet1 = &mut left[i];
+            let verlet2 = &mut right[0];
+
+            // Overlapping verlets feel the same push as touching ones so they don't get flung apart by 1 / r^2 going to infinity
+            let dist = dist_vec.length().max(verlet1.get_radius() + verlet2.get_radius());
+            let Some(direction) = dist_vec.try_normalize() else {
+                continue;
+            };
+
+            let strength = self.coulomb_constant * verlet1.get_charge() * verlet2.get_charge() * (1.0 / (dist * dist) - 1.0 / cutoff_squared);
+            let force = direction * strength; // Pushes verlet2 away from verlet1 when the charges match
+            verlet1.add_acceleration(-force / verlet1.get_mass());
+            verlet2.add_acceleration(force / verlet2.get_mass());
+        }
+    }
+
+    // Same for every pair of verlets that could collide - collision groups, layers and skipped constraints count here too
+    pub fn set_pair_potential(&mut self, pair_potential: PairPotential) {
+        self.pair_potential = pair_potential;
+    }
+    pub fn get_pair_potential(&self) -> PairPotential {
+        self.pair_potential
+    }
+
+    fn solve_pair_potential(&mut self) {
+        if self.pair_potential == PairPotential::None {
+            return;
+        }
+
+        // Range depends on the radii so search as far as the biggest pair could reach and sort it out per pair
+        let max_radius = self.verlets.iter().fold(0.0, |max_radius: f32, verlet| max_radius.max(verlet.get_radius()));
+        let max_range = self.pair_potential.range(2.0 * max_radius);
+
+        for (i, j) in self.find_pairs_within(max_range, |_| true) {
+            if !self.can_collide(i, j) {
+                continue;
+            }
+
+            let dist_vec = self.displacement(self.verlets[i].get_position(), self.verlets[j].get_position());
+
+            let (left, right) = self.verlets.split_at_mut(j);
+            let verlet1 = &mut left[i];
+            let verlet2 = &mut right[0];
+
+            let Some(direction) = dist_vec.try_normalize() else {
+                continue;
+            };
+            let contact = verlet1.get_radius() + verlet2.get_radius();
+            let force = direction * self.pair_potential.force(dist_vec.length(), contact); // Positive pushes verlet2 away from verlet1
+            verlet1.add_acceleration(-force / verlet1.get_mass());
+            verlet2.add_acceleration(force / verlet2.get_mass());
+        }
+    }
+
+    // None turns it off and fluid verlets go back to being ordinary balls - turns PBF off too since only one of them can be on
+    pub fn set_sph(&mut self, sph: Option<Sph>) {
+        if sph.is_some() {
+            self.pbf = None;
+        }
+        self.sph = sph;
+    }
+    pub fn get_sph(&self) -> Option<Sph> {
+        self.sph
+    }
+
+    // Density around every verlet from the last substep - 0 for anything that isn't fluid
+    pub fn get_fluid_densities(&self) -> &Vec<f32> {
+        &self.fluid_densities
+    }
+
+    fn solve_sph(&mut self) {
+        let Some(sph) = self.sph else {
+            return;
+        };
+        let smoothing_radius = sph.smoothing_radius;
+        let pairs = self.find_pairs_within(smoothing_radius, |verlet| verlet.is_fluid());
+        let offsets = self.fluid_offsets(&pairs, smoothing_radius);
+        self.update_fluid_densities(&pairs, &offsets, smoothing_radius);
+
+        for (&(i, j), &offset) in pairs.iter().zip(&offsets) {
+            let (density1, density2) = (self.fluid_densities[i], self.fluid_densities[j]);
+            let (pressure1, pressure2) = (sph.pressure(density1), sph.pressure(density2));
+            let dist = offset.length();
+
+            let (left, right) = self.verlets.split_at_mut(j);
+            let verlet1 = &mut left[i];
+            let verlet2 = &mut right[0];
+            let (mass1, mass2) = (verlet1.get_mass(), verlet2.get_mass());
+
+            // Averaging the two pressures keeps the push between them equal and opposite
+            let pressure_force = -spiky_gradient(offset, dist, smoothing_radius) * (pressure1 + pressure2) / 2.0;
+            let viscosity_force = (verlet2.get_velocity() - verlet1.get_velocity()) * sph.viscosity * viscosity_laplacian(dist, smoothing_radius);
+
+            // Force on 1 - the kernels are already per area so dividing by both densities turns it into an acceleration
+            let force = (pressure_force + viscosity_force) / (density1 * density2);
+            verlet1.add_acceleration(force * mass2);
+            verlet2.add_acceleration(-force * mass1);
+        }
+    }
+
+    // Offset from j to i for every fluid pair
+    fn fluid_offsets(&self, pairs: &[(usize, usize)], smoothing_radius: f32) -> Vec<Vec2> {
+        pairs.iter()
+            .map(|&(i, j)| {
+                let offset = self.displacement(self.verlets[j].get_position(), self.verlets[i].get_position());
+                // Two verlets pushed into the same corner end up exactly on top of each other where the gradient has no direction
+                // Nudging them apart along some direction that's different for every pair lets the pressure split them up again
+                if offset == Vec2::ZERO {
+                    Vec2::from_angle((i + j) as f32) * smoothing_radius * 1e-3
+                } else {
+                    offset
+                }
+            })
+            .collect()
+    }
+
+    fn update_fluid_densities(&mut self, pairs: &[(usize, usize)], offsets: &[Vec2], smoothing_radius: f32) {
+        // Every verlet counts itself too or a lone drop would have no density at all
+        self.fluid_densities = self.verlets.iter()
+            .map(|verlet| if verlet.is_fluid() { verlet.get_mass() * poly6(0.0, smoothing_radius) } else { 0.0 })
+            .collect();
+        for (&(i, j), offset) in pairs.iter().zip(offsets) {
+            let weight = poly6(offset.length_squared(), smoothing_radius);
+            self.fluid_densities[i] += self.verlets[j].get_mass() * weight;
+            self.fluid_densities[j] += self.verlets[i].get_mass() * weight;
+        }
+    }
+
+    // Position based alternative to set_sph - only one of them can be on so this turns SPH off
+    pub fn set_pbf(&mut self, pbf: Option<Pbf>) {
+        if pbf.is_some() {
+            self.sph = None;
+        }
+        self.pbf = pbf;
+    }
+    pub fn get_pbf(&self) -> Option<Pbf> {
+        self.pbf
+    }
+
+    fn is_fluid_enabled(&self) -> bool {
+        self.sph.is_some() || self.pbf.is_some()
+    }
+
+    // Runs after integrating since the positions it corrects are the predicted ones
+    fn solve_pbf(&mut self, dt: f32) {
+        let Some(pbf) = self.pbf else {
+            return;
+        };
+        let smoothing_radius = pbf.smoothing_radius;
+        let relaxation = pbf.relaxation / (smoothing_radius * smoothing_radius);
+        let pairs = self.find_pairs_within(smoothing_radius, |verlet| verlet.is_fluid());
+        let count = self.verlets.len();
+
+        for _ in 0..pbf.iterations.max(1) {
+            let offsets = self.fluid_offsets(&pairs, smoothing_radius);
+            self.update_fluid_densities(&pairs, &offsets, smoothing_radius);
+
+            // Gradient of C_i with respect to every neighbor is -m_j * grad W / rest_density and to i itself it's the sum of those
+            let mut gradient_sums = vec![Vec2::ZERO; count];
+            let mut gradient_lengths = vec![0.0; count];
+            for (&(i, j), &offset) in pairs.iter().zip(&offsets) {
+                let gradient = spiky_gradient(offset, offset.length(), smoothing_radius) / pbf.rest_density;
+                let (mass1, mass2) = (self.verlets[i].get_mass(), self.verlets[j].get_mass());
+                gradient_sums[i] += gradient * mass2;
+                gradient_sums[j] -= gradient * mass1;
+                gradient_lengths[i] += (gradient * mass2).length_squared();
+                gradient_lengths[j] += (gradient * mass1).length_squared();
+            }
+            let lambdas: Vec<f32> = (0..count)
+                .map(|i| -pbf.constraint(self.fluid_densities[i]) / (gradient_sums[i].length_squared() + gradient_lengths[i] + relaxation))
+                .collect();
+
+            let mut corrections = vec![Vec2::ZERO; count];
+            for (&(i, j), &offset) in pairs.iter().zip(&offsets) {
+                let gradient = spiky_gradient(offset, offset.length(), smoothing_radius) / pbf.rest_density;
+                let step = gradient * (lambdas[i] + lambdas[j] + pbf.tensile_instability(offset.length_squared()));
+                corrections[i] += step * self.verlets[j].get_mass();
+                corrections[j] -= step * self.verlets[i].get_mass();
+            }
+            // Moving the position without last_position changes the velocity too which is exactly what the fluid needs
+            for (verlet, &correction) in self.verlets.iter_mut().zip(&corrections) {
+                if !verlet.is_static() {
+                    verlet.set_position(verlet.get_position() + correction);
+                }
+            }
+        }
+
+        // XSPH viscosity - nudge every verlet towards the average velocity around it
+        let offsets = self.fluid_offsets(&pairs, smoothing_radius);
+        let mut velocity_changes = vec![Vec2::ZERO; count];
+        for (&(i, j), offset) in pairs.iter().zip(&offsets) {
+            let weight = poly6(offset.length_squared(), smoothing_radius);
+            let relative_velocity = self.verlets[j].get_velocity() - self.verlets[i].get_velocity();
+            velocity_changes[i] += relative_velocity * weight * self.verlets[j].get_mass() / self.fluid_densities[j];
+            velocity_changes[j] -= relative_velocity * weight * self.verlets[i].get_mass() / self.fluid_densities[i];
+        }
+        for (verlet, &velocity_change) in self.verlets.iter_mut().zip(&velocity_changes) {
+            if verlet.is_fluid() && !verlet.is_static() {
+                verlet.set_velocity(verlet.get_velocity() + velocity_change * pbf.viscosity, dt);
+            }
+        }
+    }
+
+    // None turns heat off - temperatures stay where they are but nothing changes them or floats
+    // Verlets nobody gave a temperature start at ambient so turning it on doesn't make everything float or sink
+    pub fn set_thermal(&mut self, thermal: Option<Thermal>) {
+        self.thermal = thermal;
+        for verlet in &mut self.verlets {
+            start_at_ambient(verlet, self.thermal.as_ref());
+        }
+    }
+    pub fn get_thermal(&self) -> Option<&Thermal> {
+        self.thermal.as_ref()
+    }
+    #[allow(dead_code)]
+    pub fn get_thermal_mut(&mut self) -> Option<&mut Thermal> {
+        self.thermal.as_mut()
+    }
+
+    fn apply_wall_heat(&mut self, dt: f32) {
+        let Some(thermal) = &self.thermal else {
+            return;
+        };
+        let Some(reach) = thermal.wall_heat.iter().map(|wall_heat| wall_heat.reach).reduce(f32::max) else {
+            return;
+        };
+
+        for verlet in &mut self.verlets {
+            // Pretending the verlet is reach bigger finds the walls it's near as well as the ones it's touching
+            let Some((correct_position, wall_normal)) = self.boundary.world_contact(&self.boundary_transform, verlet.get_position(), verlet.get_radius() + reach) else {
+                continue;
+            };
+            let Some(wall_heat) = thermal.wall_heat_at(wall_normal) else {
+                continue;
+            };
+            let gap = reach - (correct_position - verlet.get_position()).length();
+            if gap <= wall_heat.reach {
+                let temperature = verlet.get_temperature();
+                if verlet.is_sleeping() && (wall_heat.temperature - temperature).abs() >= thermal.wake_difference {
+                    verlet.set_sleeping(false);
+                }
+                verlet.set_temperature(temperature + (wall_heat.temperature - temperature) * (wall_heat.transfer * dt).min(1.0));
+            }
+        }
+    }
+
+    // Conduction between every touching pair the broadphase found then cooling and buoyancy for everything
+    // Runs before solve_collisions pushes the pairs apart so resting contacts still count as touching
+    // Two sleeping verlets never make it into the broadphase so heat reaching one wakes it and it passes it on awake
+    fn solve_heat(&mut self, collisions: &[(usize, usize)], dt: f32) {
+        let Some(thermal) = &self.thermal else {
+            return;
+        };
+
+        for &(i, j) in collisions {
+            let dist = self.displacement(self.verlets[i].get_position(), self.verlets[j].get_position()).length();
+            let (radius1, radius2) = (self.verlets[i].get_radius(), self.verlets[j].get_radius());
+            if dist >= radius1 + radius2 + radius1.min(radius2) * CONTACT_SLOP {
+                continue;
+            }
+
+            // Heat flows at the rate of the smaller one so the total mass * temperature stays the same
+            let (mass1, mass2) = (self.verlets[i].get_mass(), self.verlets[j].get_mass());
+            let (temperature1, temperature2) = (self.verlets[i].get_temperature(), self.verlets[j].get_temperature());
+            if (temperature2 - temperature1).abs() >= thermal.wake_difference {
+                for k in [i, j] {
+                    if self.verlets[k].is_sleeping() {
+                        self.verlets[k].set_sleeping(false);
+                    }
+                }
+            }
+            let heat = (temperature2 - temperature1) * (thermal.conductivity * dt).min(0.5) * 2.0 * mass1 * mass2 / (mass1 + mass2);
+            self.verlets[i].set_temperature(temperature1 + heat / mass1);
+            self.verlets[j].set_temperature(temperature2 - heat / mass2);
+        }
+
+        for verlet in &mut self.verlets {
+            let temperature = verlet.get_temperature();
+            verlet.set_temperature(temperature + (thermal.ambient_temperature - temperature) * (thermal.cooling * dt).min(1.0));
+
+            let buoyancy = thermal.buoyancy(verlet.get_temperature(), self.gravity);
+            // Hot enough to float on its own so it shouldn't stay asleep at the bottom of the pile
+            if verlet.is_sleeping() && buoyancy.length() > self.gravity.length() && buoyancy.dot(self.gravity) < 0.0 {
+                verlet.set_sleeping(false);
+            }
+            verlet.add_acceleration(buoyancy);
+        }
+    }
+
+    pub fn add_force_field(&mut self, force_field: Box<dyn ForceField>) -> usize {
+        self.force_fields.push(force_field);
+        self.force_fields.len() - 1
+    }
+    pub fn get_force_fields(&self) -> &Vec<Box<dyn ForceField>> {
+        &self.force_fields
+    }
+    #[allow(dead_code)]
+    pub fn get_force_fields_mut(&mut self) -> &mut Vec<Box<dyn ForceField>> {
+        &mut self.force_fields
+    }
+    pub fn clear_force_fields(&mut self) {
+        self.force_fields.clear();
+    }
+
+    #[allow(dead_code)]
+    pub fn get_gravity(&self) -> Vec2 {
+        self.gravity
+    }
+    // What's holding a pile up depends on which way is down
+    pub fn set_gravity(&mut self, gravity: Vec2) {
+        if gravity != self.gravity {
+            self.wake_all();
+        }
+        self.gravity = gravity;
+    }
+
+    pub fn get_integrator(&self) -> Integrator {
+        self.integrator
+    }
+    pub fn set_integrator(&mut self, integrator: Integrator) {
+        self.integrator = integrator;
+    }
+
+    #[allow(dead_code)]
+    pub fn get_subdivision(&self) -> usize {
+        self.subdivision
+    }
+    pub fn set_subdivision(&mut self, subdivision: usize) {
+        self.subdivision = subdivision.max(1);
+    }
+
+    // Verlets slower than sleep_speed for sleep_time seconds fall asleep and stop being integrated or collided with each other
+    // Off by default
+    pub fn set_sleeping_enabled(&mut self, sleeping_enabled: bool, sleep_speed: f32, sleep_time: f32) {
+        self.sleeping_enabled = sleeping_enabled;
+        self.sleep_speed = sleep_speed;
+        self.sleep_time = sleep_time;
+        if !sleeping_enabled {
+            self.wake_all();
+        }
+    }
+    #[allow(dead_code)]
+    pub fn is_sleeping_enabled(&self) -> bool {
+        self.sleeping_enabled
+    }
+    pub fn get_sleeping_count(&self) -> usize {
+        self.verlets.iter().filter(|verlet| verlet.is_sleeping()).count()
+    }
+    pub fn wake_all(&mut self) {
+        for verlet in &mut self.verlets {
+            if verlet.is_sleeping() {
+                verlet.set_sleeping(false);
+            }
+        }
+    }
+
+    // Islands are verlets tied together by springs, shape matches or rigid bodies and they fall asleep and wake up as one
+    // Otherwise a sleeping half of a cloth would hang off the awake half like it was pinned
+    // disturbed are sleepers next to something moving this substep - only their islands can have lost what held them up
+    // Removing verlets, colliders or walls wakes whatever they held straight away so nothing else needs checking
+    fn update_sleeping(&mut self, dt: f32, disturbed: &[usize]) {
+        // Moving walls can drop the floor away at any time
+        let can_sleep = !self.boundary_transform.is_moving();
+        for verlet in &mut self.verlets {
+            if verlet.is_static() {
+                continue;
+            }
+            // Fluid only touches its neighbors through pressure so nothing would ever wake it back up
+            if verlet.get_velocity().length() < self.sleep_speed && !verlet.is_fluid() {
+                verlet.set_sleep_timer(verlet.get_sleep_timer() + dt);
+            } else {
+                verlet.set_sleep_timer(0.0);
+            }
+        }
+
+        let islands = self.find_islands();
+        // Floating still with no gravity is a fine place to sleep
+        if self.gravity != Vec2::ZERO && !disturbed.is_empty() {
+            self.wake_unsupported(&islands, disturbed);
+        }
+
+        let mut has_awake = vec![false; self.verlets.len()];
+        let mut all_tired = vec![true; self.verlets.len()];
+        for (i, verlet) in self.verlets.iter().enumerate() {
+            if verlet.is_static() {
+                continue;
+            }
+            has_awake[islands[i]] = true;
+            if verlet.get_sleep_timer() < self.sleep_time || !can_sleep {
+                all_tired[islands[i]] = false;
+            }
+        }
+
+        for (i, verlet) in self.verlets.iter_mut().enumerate() {
+            if verlet.is_anchored() || !has_awake[islands[i]] {
+                continue;
+            }
+            // Everything awake in the island has been still long enough or something in it is still moving and wakes the rest
+            if all_tired[islands[i]] != verlet.is_sleeping() {
+                verlet.set_sleeping(all_tired[islands[i]]);
+            }
+        }
+    }
+
+    // Sleeping verlets the broadphase paired with something that can move - a merge or split since then can shift indices so they get checked again
+    fn sleepers_near_moving(&self, collisions: &[(usize, usize)]) -> Vec<usize> {
+        let mut disturbed = vec![];
+        for &(i, j) in collisions {
+            let (verlet1, verlet2) = (&self.verlets[i], &self.verlets[j]);
+            if verlet1.is_sleeping() && !verlet2.is_static() {
+                disturbed.push(i);
+            }
+            if verlet2.is_sleeping() && !verlet1.is_static() {
+                disturbed.push(j);
+            }
+        }
+        disturbed
+    }
+
+    // A disturbed sleeping island that isn't resting on anything anymore had its support rolled or dragged off so it has to fall
+    // Runs on the grid the broadphase filled this substep - sleeping verlets haven't moved since
+    fn wake_unsupported(&mut self, islands: &[usize], disturbed: &[usize]) {
+        let mut checking = vec![false; self.verlets.len()];
+        for &i in disturbed {
+            if i < self.verlets.len() && self.verlets[i].is_sleeping() {
+                checking[islands[i]] = true;
+            }
+        }
+        if !checking.contains(&true) {
+            return;
+        }
+
+        // Tied to an anchor holds the whole island up like a hanging cloth
+        let mut supported = vec![false; self.verlets.len()];
+        let mut pin = |i: usize, j: usize| {
+            if self.verlets[j].is_anchored() && !self.verlets[i].is_anchored() {
+                supported[islands[i]] = true;
+            }
+        };
+        for &(i, j, _) in &self.constraints {
+            pin(i, j);
+            pin(j, i);
+        }
+        let groups = self.shape_matches.iter().map(|shape_match| shape_match.get_indices())
+            .chain(self.rigid_bodies.iter().map(|rigid_body| rigid_body.get_indices()));
+        for indices in groups {
+            if let Some(&anchor) = indices.iter().find(|&&i| self.verlets[i].is_anchored()) {
+                for &i in indices {
+                    pin(i, anchor);
+                }
+            }
+        }
+
+        let max_radius = self.verlets.iter().map(|verlet| verlet.get_radius()).fold(0.0, f32::max);
+        let down = self.gravity.normalize();
+        let mut nearby: Vec<usize> = vec![];
+        for i in 0..self.verlets.len() {
+            if checking[islands[i]] && self.verlets[i].is_sleeping() && !supported[islands[i]]
+                && self.is_resting_on_anything(i, islands, down, max_radius, &mut nearby) {
+                supported[islands[i]] = true;
+            }
+        }
+
+        for (i, verlet) in self.verlets.iter_mut().enumerate() {
+            if checking[islands[i]] && verlet.is_sleeping() && !supported[islands[i]] {
+                verlet.set_sleeping(false);
+            }
+        }
+    }
+
+    // Wall, collider or a verlet from another island within CONTACT_SLOP of touching somewhere under it
+    fn is_resting_on_anything(&self, i: usize, islands: &[usize], down: Vec2, max_radius: f32, nearby: &mut Vec<usize>) -> bool {
+        let (position, radius) = (self.verlets[i].get_position(), self.verlets[i].get_radius());
+        let slop = radius * CONTACT_SLOP;
+        // Wall normals point out of the container so the floor's points down
+        if let Some((_, wall_normal)) = self.boundary.world_contact(&self.boundary_transform, position, radius + slop)
+            && wall_normal.dot(down) > SUPPORT_MIN_COS {
+            return true;
+        }
+
+        // Collider normals point away from the collider so one underneath points up
+        self.colliders_near(position, radius + slop, nearby);
+        let on_collider = nearby.iter().any(|&index| {
+            self.colliders[index].get_shape().contact(position, radius + slop)
+                .is_some_and(|(_, normal)| normal.dot(down) < -SUPPORT_MIN_COS)
+        });
+        if on_collider {
+            return true;
+        }
+
+        let (cell_x, cell_y) = self.cell_of(position);
+        let cell_index = cell_y * self.grid_width + cell_x;
+        let reach = radius + max_radius + slop;
+        let reach_x = (reach / self.grid_cell_size.x).ceil() as isize;
+        let reach_y = (reach / self.grid_cell_size.y).ceil() as isize;
+        for dy in -reach_y..=reach_y {
+            for dx in -reach_x..=reach_x {
+                let neighbor_index = if dx == 0 && dy == 0 { Some(cell_index) } else { self.neighbor_cell(cell_index, dx, dy) };
+                let Some(neighbor_index) = neighbor_index else {
+                    continue;
+                };
+                for &j in &self.grid[neighbor_index] {
+                    if j == i || j >= self.verlets.len() || islands[j] == islands[i] {
+                        continue;
+                    }
+                    let touching = radius + self.verlets[j].get_radius() + slop;
+                    let offset = self.displacement(position, self.verlets[j].get_position());
+                    if offset.length_squared() < touching * touching && offset.normalize_or_zero().dot(down) > SUPPORT_MIN_COS {
+                        return true;
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    // Union find over everything that links verlets - gives back the root of every verlet's island
+    // Anchored verlets are left out so two cloths pinned to the same point don't become one island
+    fn find_islands(&self) -> Vec<usize> {
+        fn find(parents: &mut [usize], mut i: usize) -> usize {
+            while parents[i] != i {
+                parents[i] = parents[parents[i]];
+                i = parents[i];
+            }
+            i
+        }
+
+        let mut parents: Vec<usize> = (0..self.verlets.len()).collect();
+        let is_free = |i: &usize| !self.verlets[*i].is_anchored();
+
+        for &(i, j, _) in &self.constraints {
+            if is_free(&i) && is_free(&j) {
+                let (root_i, root_j) = (find(&mut parents, i), find(&mut parents, j));
+                parents[root_i] = root_j;
+            }
+        }
+        let groups = self.shape_matches.iter().map(|shape_match| shape_match.get_indices())
+            .chain(self.rigid_bodies.iter().map(|rigid_body| rigid_body.get_indices()));
+        for indices in groups {
+            let free: Vec<usize> = indices.iter().copied().filter(is_free).collect();
+            for pair in free.windows(2) {
+                let (root_i, root_j) = (find(&mut parents, pair[0]), find(&mut parents, pair[1]));
+                parents[root_i] = root_j;
+            }
+        }
+
+        (0..self.verlets.len()).map(|i| find(&mut parents, i)).collect()
+    }
+
+    fn apply_periodic_wrap(&mut self) {
+        if !self.boundary.is_periodic() {
+            return;
+        }
+        for verlet in &mut self.verlets {
+            let position = verlet.get_position();
+            let wrapped = self.boundary.world_wrap(&self.boundary_transform, position);
+            if wrapped != position {
+                verlet.translate(wrapped - position);
+            }
+        }
+    }
+
+    // Offset from one position to another - across the seam if that's shorter on a periodic boundary
+    fn displacement(&self, from: Vec2, to: Vec2) -> Vec2 {
+        self.boundary.world_minimum_image(&self.boundary_transform, to - from)
+    }
+
     fn apply_wall_constraints(&mut self, dt: f32) {
         let coefficient_of_restitution = 1.0;
 
         for verlet in &mut self.verlets {
-            let dist_to_cen = verlet.get_position();
-            let dist = dist_to_cen.length();
-            
-            if dist > self.constraint_radius - verlet.get_radius() {
-                let dist_norm = dist_to_cen.normalize();
+            if verlet.is_anchored() {
+                continue;
+            }
+
+            // A corner is two walls at once so go again after the first push
+            for _ in 0..2 {
+                let Some((correct_position, wall_normal)) = self.boundary.world_contact(&self.boundary_transform, verlet.get_position(), verlet.get_radius()) else {
+                    break;
+                };
+                let wall_velocity = self.boundary_transform.velocity_at(correct_position);
 
-                let vel = verlet.get_velocity();
-                let v_norm = vel.project_onto(dist_norm);
+                // Sleeping verlets only notice the wall if it's moving into them
+                if verlet.is_sleeping() {
+                    if wall_velocity.length() <= self.sleep_speed {
+                        break;
+                    }
+                    verlet.set_sleeping(false);
+                }
 
-                let correct_position = dist_norm * (self.constraint_radius - verlet.get_radius());
+                // Bounce in the wall's frame so a moving wall hands its velocity over
+                let vel = verlet.get_velocity() - wall_velocity;
                 verlet.set_position(correct_position);
-                verlet.set_velocity( (vel - 2.0 * v_norm) * coefficient_of_restitution, dt); // Just push the portion normal to the wall inverse
+                verlet.set_velocity(wall_velocity + bounce(vel, -wall_normal, coefficient_of_restitution, self.wall_friction), dt);
             }
         }
     }
 
-    // 1322 balls - 6 rad - 8 subs - 16 ms
-    fn find_collisions_space_partitioning(&mut self) -> Vec<(usize, usize)> {
-        let mut collisions: Vec<(usize, usize)> = vec![];
+    fn solve_colliders(&mut self, dt: f32) {
+        if self.colliders.is_empty() {
+            return;
+        }
 
-        for cell in &mut self.grid {
-            cell.clear();
+        let mut nearby: Vec<usize> = vec![];
+        for i in 0..self.verlets.len() {
+            if self.verlets[i].is_static() {
+                continue;
+            }
+
+            self.colliders_near(self.verlets[i].get_position(), self.verlets[i].get_radius(), &mut nearby);
+            for &index in &nearby {
+                let collider = &self.colliders[index];
+                let verlet = &mut self.verlets[i];
+                if let Some((correct_position, normal)) = collider.get_shape().contact(verlet.get_position(), verlet.get_radius()) {
+                    let vel = collider.bounce(verlet.get_velocity(), normal);
+                    verlet.set_position(correct_position);
+                    verlet.set_velocity(vel, dt);
+                }
+            }
         }
+    }
 
-        for (i, verlet) in self.verlets.iter().enumerate() {
-            let pos = verlet.get_position();
-            
-            let cell_x = ((pos.x + self.constraint_radius) / self.cell_size).floor() as usize;
-            let cell_y = ((pos.y + self.constraint_radius) / self.cell_size).floor() as usize;
-            
-            let cell_index = (cell_y * self.grid_size) + cell_x;
-            if cell_index < self.grid.len() {
-                self.grid[cell_index].push(i);
+    // Every collider whose cells a circle overlaps - a big collider sits in many cells so the same one can come up more than once
+    fn colliders_near(&self, position: Vec2, radius: f32, nearby: &mut Vec<usize>) {
+        nearby.clear();
+        let Some((min_cell, max_cell)) = self.collider_cells(position - radius, position + radius) else {
+            return;
+        };
+        for y in min_cell.1..=max_cell.1 {
+            for x in min_cell.0..=max_cell.0 {
+                nearby.extend(&self.collider_grid[y * self.collider_grid_width + x]);
             }
         }
+        nearby.sort_unstable();
+        nearby.dedup();
+    }
 
-        
-        let neighbor_offsets: [usize; 4] = [
-            1,                  // right
-            self.grid_size + 1, // bottom-right
-            self.grid_size,     // bottom
-            self.grid_size - 1  // bottom-left
-        ];
+    // 1322 balls - 6 rad - 8 subs - 16 ms
+    fn find_collisions_space_partitioning(&mut self) -> Vec<(usize, usize)> {
+        let mut collisions: Vec<(usize, usize)> = vec![];
 
+        self.fill_grid();
+        
+        let mut neighbors = vec![];
         for cell_index in 0..self.grid.len() {
-            // Calculate x and y if needed
-            
             let particles_in_cell = &self.grid[cell_index];
             let particles_in_cell_count = particles_in_cell.len();
+            if particles_in_cell_count == 0 {
+                continue;
+            }
+            self.neighbor_cells_after(cell_index, 1, 1, &mut neighbors);
+            // A cell where everything is asleep or anchored only needs pairs with the moving cells next to it
+            let moving = self.moving_cells[cell_index];
+            if !moving {
+                neighbors.retain(|&neighbor_index| self.moving_cells[neighbor_index]);
+            }
 
             for i in 0..particles_in_cell_count {
                 let particle_i = particles_in_cell[i];
                 
                 // Check against other particles in the same cell
-                for j in (i + 1)..particles_in_cell_count {
-                    let particle_j = particles_in_cell[j];
-                    collisions.push((particle_i.min(particle_j), particle_i.max(particle_j)));
+                if moving {
+                    for &particle_j in &particles_in_cell[(i + 1)..] {
+                        if self.can_collide(particle_i, particle_j) {
+                            collisions.push((particle_i.min(particle_j), particle_i.max(particle_j)));
+                        }
+                    }
                 }
 
                 // Check against particles in neighboring cells
-                for &offset in &neighbor_offsets {
-                    let neighbor_index = cell_index + offset;
-                    
-                    // Boundary checking
-                    if neighbor_index < self.grid.len() {
-                        // Edge case checking (for right/left edges)
-                        let x = cell_index % self.grid_size;
-                        if (offset == 1 && x == self.grid_size - 1) ||                // right edge
-                        (offset == self.grid_size + 1 && x == self.grid_size - 1) || // bottom-right at right edge
-                        (offset == self.grid_size - 1 && x == 0) {                 // bottom-left at left edge
-                            continue;
-                        }
-                        
-                        // Check against all particles in neighboring cell
-                        for &particle_j in &self.grid[neighbor_index] { 
+                for &neighbor_index in &neighbors {
+                    // Check against all particles in neighboring cell
+                    for &particle_j in &self.grid[neighbor_index] { 
+                        if self.can_collide(particle_i, particle_j) {
                             collisions.push((particle_i.min(particle_j), particle_i.max(particle_j)));
                         }
                     }
@@ -146,48 +1159,405 @@ impl Solver {
         collisions
     }
 
+    fn fill_grid(&mut self) {
+        for cell in &mut self.grid {
+            cell.clear();
+        }
+        self.moving_cells.clear();
+        self.moving_cells.resize(self.grid.len(), false);
+
+        for (i, verlet) in self.verlets.iter().enumerate() {
+            let pos = verlet.get_position();
+            
+            let (cell_x, cell_y) = self.cell_of(pos);
+            
+            let cell_index = (cell_y * self.grid_width) + cell_x;
+            self.grid[cell_index].push(i);
+            self.moving_cells[cell_index] |= !verlet.is_static();
+        }
+    }
+
+    // Every pair the filter lets through that's closer than range (i < j) - reuses the grid find_collisions_space_partitioning just filled
+    // range can be bigger than a cell so it looks as many cells out as it needs to
+    fn find_pairs_within(&self, range: f32, filter: impl Fn(&Verlet) -> bool) -> Vec<(usize, usize)> {
+        let mut pairs = vec![];
+        let range_squared = range * range;
+
+        let mut reach_x = (range / self.grid_cell_size.x).ceil() as isize;
+        let mut reach_y = (range / self.grid_cell_size.y).ceil() as isize;
+        if self.boundary.is_periodic() {
+            // Halfway around in both directions already covers every column and row
+            reach_x = reach_x.min(self.grid_width as isize / 2);
+            reach_y = reach_y.min(self.grid_height as isize / 2);
+        }
+
+        let mut within_range = |i: usize, j: usize| {
+            let dist_vec = self.displacement(self.verlets[i].get_position(), self.verlets[j].get_position());
+            if dist_vec.length_squared() < range_squared && filter(&self.verlets[j]) {
+                pairs.push((i.min(j), i.max(j)));
+            }
+        };
+
+        let mut neighbors = vec![];
+        for cell_index in 0..self.grid.len() {
+            let particles_in_cell = &self.grid[cell_index];
+            if particles_in_cell.is_empty() {
+                continue;
+            }
+            self.neighbor_cells_after(cell_index, reach_x, reach_y, &mut neighbors);
+
+            for (k, &particle_i) in particles_in_cell.iter().enumerate() {
+                if !filter(&self.verlets[particle_i]) {
+                    continue;
+                }
+
+                for &particle_j in &particles_in_cell[(k + 1)..] {
+                    within_range(particle_i, particle_j);
+                }
+
+                for &neighbor_index in &neighbors {
+                    for &particle_j in &self.grid[neighbor_index] {
+                        within_range(particle_i, particle_j);
+                    }
+                }
+            }
+        }
+
+        pairs
+    }
+
+    // Every different cell within reach that comes after cell_index so each pair of cells only comes up once
+    // On a periodic grid only 2 or so cells across going left and right wrap onto the same cell which would solve its pairs twice
+    fn neighbor_cells_after(&self, cell_index: usize, reach_x: isize, reach_y: isize, neighbors: &mut Vec<usize>) {
+        neighbors.clear();
+        for dy in -reach_y..=reach_y {
+            for dx in -reach_x..=reach_x {
+                if let Some(neighbor_index) = self.neighbor_cell(cell_index, dx, dy)
+                    && neighbor_index > cell_index
+                    && !neighbors.contains(&neighbor_index) {
+                    neighbors.push(neighbor_index);
+                }
+            }
+        }
+    }
+
+    // Cell next to cell_index - past the edge there's nothing unless the boundary wraps around to the other side
+    fn neighbor_cell(&self, cell_index: usize, dx: isize, dy: isize) -> Option<usize> {
+        let (width, height) = (self.grid_width as isize, self.grid_height as isize);
+        let mut x = (cell_index % self.grid_width) as isize + dx;
+        let mut y = (cell_index / self.grid_width) as isize + dy;
+
+        if self.boundary.is_periodic() {
+            x = x.rem_euclid(width);
+            y = y.rem_euclid(height);
+        } else if x < 0 || x >= width || y < 0 || y >= height {
+            return None;
+        }
+
+        let neighbor_index = (y * width + x) as usize;
+        // A grid only 1 cell across wraps back onto itself
+        (neighbor_index != cell_index).then_some(neighbor_index)
+    }
+
+    // Filtering every find_collisions_* routine goes through so pairs that should never touch don't even reach solve_collisions
+    fn can_collide(&self, i: usize, j: usize) -> bool {
+        let verlet1 = &self.verlets[i];
+        let verlet2 = &self.verlets[j];
+
+        if !verlet1.collides_with(verlet2) {
+            return false;
+        }
+        // Same non zero group never collides - like a whole cloth or ragdoll ignoring itself
+        if verlet1.get_collision_group() != 0 && verlet1.get_collision_group() == verlet2.get_collision_group() {
+            return false;
+        }
+        // Particles in the same rigid body can't move relative to each other anyway
+        if verlet1.get_rigid_body().is_some() && verlet1.get_rigid_body() == verlet2.get_rigid_body() {
+            return false;
+        }
+        // Neither can move - this is also what lets a sleeping pile skip all its pair tests
+        if verlet1.is_static() && verlet2.is_static() {
+            return false;
+        }
+        // Fluid pressure keeps fluid apart so a hard contact would just make it bounce like sand
+        if self.is_fluid_enabled() && verlet1.is_fluid() && verlet2.is_fluid() {
+            return false;
+        }
+        // Springs already keep these apart so colliding just fights the spring
+        if self.skip_constrained_collisions && self.constrained_pairs.contains(&(i.min(j), i.max(j))) {
+            return false;
+        }
+        true
+    }
+
+    pub fn set_skip_constrained_collisions(&mut self, skip: bool) {
+        self.skip_constrained_collisions = skip;
+    }
+    #[allow(dead_code)]
+    pub fn get_skip_constrained_collisions(&self) -> bool {
+        self.skip_constrained_collisions
+    }
+
     fn solve_collisions(&mut self, collisions: Vec<(usize, usize)>, dt: f32) {
         let coefficient_of_restitution = 0.93;
+        let sleeping_enabled = self.sleeping_enabled;
+        let sleep_speed = self.sleep_speed;
+
+        // Every verlet can only merge or split once a substep so the indices stay valid until they're all applied
+        let merge_rules = self.merge_rules;
+        let mut can_change = if merge_rules.is_some() { self.free_verlets() } else { vec![] };
+        let mut merges: Vec<(usize, usize)> = vec![];
+        let mut splits: Vec<(usize, f32)> = vec![];
 
         for (i, j) in collisions {
+            let collision_axis = self.displacement(self.verlets[j].get_position(), self.verlets[i].get_position()); // This is the distance vector between the two verlets which is also the collision_axis vector to the plane of collison
+
             let (left, right) = self.verlets.split_at_mut(j);
             let verlet1 = &mut left[i];
             let verlet2 = &mut right[0];
 
-            let collision_axis = verlet1.get_position() - verlet2.get_position(); // This is the distance vector between the two verlets which is also the collision_axis vector to the plane of collison
+            // solve_collisions can be handed pairs from any broadphase so the layers get checked here too
+            if !verlet1.collides_with(verlet2) {
+                continue;
+            }
+
             let dist = collision_axis.length();
             let min_dist = verlet1.get_radius() + verlet2.get_radius();
 
             if dist < min_dist {
+                if sleeping_enabled {
+                    wake_on_contact(verlet1, verlet2, sleep_speed);
+                }
+
                 let collision_normal = collision_axis.normalize();
-                let collision_perp_normal = collision_axis.perp().normalize();
+
+                if let Some(rules) = merge_rules && can_change[i] && can_change[j] {
+                    // Already separating counts as 0 so a merge_speed of 0 really never merges
+                    let closing_speed = (verlet2.get_velocity() - verlet1.get_velocity()).dot(collision_normal).max(0.0);
+                    let (radius1, radius2) = (verlet1.get_radius(), verlet2.get_radius());
+                    if closing_speed < rules.merge_speed && (radius1 * radius1 + radius2 * radius2).sqrt() <= rules.max_radius {
+                        merges.push((i, j));
+                        (can_change[i], can_change[j]) = (false, false);
+                        continue;
+                    }
+                    let bigger = if radius1 >= radius2 { i } else { j };
+                    if closing_speed > rules.split_speed && rules.can_split(radius1.max(radius2)) {
+                        splits.push((bigger, closing_speed));
+                        can_change[bigger] = false;
+                    }
+                }
                 let overlap = (min_dist - dist) * 1.1;
-                
-                let vel1 = verlet1.get_velocity().project_onto(collision_normal);
-                let vel1_perp = verlet1.get_velocity().project_onto(collision_perp_normal);
-                let vel2 = verlet2.get_velocity().project_onto(collision_normal);
-                let vel2_perp = verlet2.get_velocity().project_onto(collision_perp_normal);
-                let m1 = verlet1.get_mass();
-                let m2 = verlet2.get_mass();
 
-                let vel1f = (vel1 * (m1 - m2) + 2.0 * m2 *  vel2) / (m1 + m2);
-                let vel2f = (vel2 * (m2 - m1) + 2.0 * m1 *  vel1) / (m1 + m2);
+                // An anchored or sleeping verlet acts like a wall with infinite mass so the other one takes the whole push
+                let (share1, share2) = if verlet1.is_static() {
+                    (0.0, 1.0)
+                } else if verlet2.is_static() {
+                    (1.0, 0.0)
+                } else {
+                    (0.5, 0.5)
+                };
+
+                // Velocities have to be read before moving since they come from the position
+                let (vel1f, vel2f) = bounce_velocities(verlet1, verlet2, collision_normal);
+
+                verlet1.set_position(verlet1.get_position() + collision_normal * overlap * share1);
+                verlet2.set_position(verlet2.get_position() -  collision_normal * overlap * share2);
+
+                if !verlet1.is_static() {
+                    verlet1.set_velocity(vel1f * coefficient_of_restitution, dt);
+                }
+                if !verlet2.is_static() {
+                    verlet2.set_velocity(vel2f * coefficient_of_restitution, dt);
+                }
+            }
+        }
+
+        if !merges.is_empty() || !splits.is_empty() {
+            self.merge_and_split(&merges, &splits, dt);
+        }
+    }
+
+    // Only set with merge rules on - see MergeRules for what can't merge or split
+    pub fn set_merge_rules(&mut self, merge_rules: Option<MergeRules>) {
+        self.merge_rules = merge_rules;
+    }
+    pub fn get_merge_rules(&self) -> Option<MergeRules> {
+        self.merge_rules
+    }
+
+    // Verlets not held by anything - merging or splitting one of these can't break a body
+    fn free_verlets(&self) -> Vec<bool> {
+        let mut free: Vec<bool> = self.verlets.iter()
+            .map(|verlet| !verlet.is_static() && !verlet.is_fluid() && verlet.get_rigid_body().is_none())
+            .collect();
+        for &(i, j, _) in &self.constraints {
+            (free[i], free[j]) = (false, false);
+        }
+        for shape_match in &self.shape_matches {
+            for &i in shape_match.get_indices() {
+                free[i] = false;
+            }
+        }
+        free
+    }
+
+    fn merge_and_split(&mut self, merges: &[(usize, usize)], splits: &[(usize, f32)], dt: f32) {
+        let Some(rules) = self.merge_rules else {
+            return;
+        };
+
+        // i becomes the merged verlet and j goes away - everything that adds up is weighted by mass
+        let mut keep = vec![true; self.verlets.len()];
+        for &(i, j) in merges {
+            let offset = self.displacement(self.verlets[i].get_position(), self.verlets[j].get_position());
+            let other = self.verlets[j].clone();
+            let verlet = &mut self.verlets[i];
+
+            let (mass1, mass2) = (verlet.get_mass(), other.get_mass());
+            let mass = mass1 + mass2;
+            let velocity = (verlet.get_velocity() * mass1 + other.get_velocity() * mass2) / mass;
+            let radius = (verlet.get_radius().powi(2) + other.get_radius().powi(2)).sqrt();
+
+            verlet.set_position(verlet.get_position() + offset * mass2 / mass);
+            verlet.set_velocity(velocity, dt);
+            verlet.set_radius(radius);
+            verlet.set_density(mass / (std::f32::consts::PI * radius * radius));
+            verlet.set_color((verlet.get_color() * mass1 + other.get_color() * mass2) / mass);
+            verlet.set_temperature((verlet.get_temperature() * mass1 + other.get_temperature() * mass2) / mass);
+            verlet.set_charge(verlet.get_charge() + other.get_charge());
+            verlet.set_continuous_collision(verlet.is_continuous_collision() || other.is_continuous_collision());
+            keep[j] = false;
+        }
+
+        // Pieces go in a ring inside the old circle and fly apart on top of the old velocity so momentum doesn't change
+        // Staying inside means they can't land in anything the old one wasn't already touching - they overlap each other a bit which the next collision pass sorts out
+        // The first piece reuses the old verlet and the rest go on the end so no index moves
+        for &(i, closing_speed) in splits {
+            let original = self.verlets[i].clone();
+            let velocity = original.get_velocity();
+            let fragment_radius = rules.fragment_radius(original.get_radius());
+            let ring_radius = original.get_radius() - fragment_radius;
+            let charge = original.get_charge() / rules.fragments as f32;
+
+            for fragment in 0..rules.fragments {
+                let direction = Vec2::from_angle(i as f32 + fragment as f32 * std::f32::consts::TAU / rules.fragments as f32);
+                let mut piece = original.clone();
+                piece.set_radius(fragment_radius);
+                piece.set_charge(charge);
+                piece.set_position(original.get_position() + direction * ring_radius);
+                piece.set_velocity(velocity + direction * closing_speed * rules.fragment_speed, dt);
+                if fragment == 0 {
+                    self.verlets[i] = piece;
+                } else {
+                    self.verlets.push(piece);
+                    keep.push(true);
+                }
+            }
+        }
+
+        self.retain_verlets(&keep);
+        // Things later in the substep still look things up in the grid
+        self.fill_grid();
+    }
+
+    // Swept circle tests for verlets that move so far in one substep they could skip past a wall or another ball
+    // Runs after integrating so the sweep is last_position -> position and the grid still has where everything started
+    // https://lisyarus.github.io/blog/posts/perfect-collisions.html
+    fn solve_continuous_collisions(&mut self, dt: f32) {
+        let coefficient_of_restitution = 0.93;
+        let wall_coefficient_of_restitution = 1.0;
+
+        for i in 0..self.verlets.len() {
+            let verlet = &self.verlets[i];
+            if !verlet.is_continuous_collision() || verlet.is_static() {
+                continue;
+            }
+
+            let start = verlet.get_last_position();
+            let motion = verlet.get_position() - start;
+            let radius = verlet.get_radius();
+
+            // Every cell the sweep passes over plus a margin for the other ball's radius
+            let margin = Vec2::splat(radius + self.cell_size);
+            let (min_x, min_y) = self.cell_of(start.min(start + motion) - margin);
+            let (max_x, max_y) = self.cell_of(start.max(start + motion) + margin);
+
+            let mut earliest: Option<(f32, usize)> = None;
+            for y in min_y..=max_y {
+                for x in min_x..=max_x {
+                    for &j in &self.grid[y * self.grid_width + x] {
+                        if j == i || !self.can_collide(i, j) {
+                            continue;
+                        }
+
+                        let other = &self.verlets[j];
+                        let other_start = other.get_last_position();
+                        let other_motion = other.get_position() - other_start;
+                        let min_dist = radius + other.get_radius();
+
+                        if let Some(t) = time_of_impact(self.displacement(other_start, start), motion - other_motion, min_dist)
+                            && earliest.is_none_or(|(best, _)| t < best) {
+                            earliest = Some((t, j));
+                        }
+                    }
+                }
+            }
+
+            if let Some((t, j)) = earliest {
+                // Rewind both to the moment they touch, bounce them there and use up the rest of the step with the new velocities
+                let (first, second) = (i.min(j), i.max(j));
+                let (boundary, boundary_transform) = (&self.boundary, &self.boundary_transform);
+                let (left, right) = self.verlets.split_at_mut(second);
+                let (verlet1, verlet2) = (&mut left[first], &mut right[0]);
+                if self.sleeping_enabled {
+                    wake_on_contact(verlet1, verlet2, self.sleep_speed);
+                }
+
+                let contact1 = verlet1.get_last_position() + (verlet1.get_position() - verlet1.get_last_position()) * t;
+                let contact2 = verlet2.get_last_position() + (verlet2.get_position() - verlet2.get_last_position()) * t;
+                let collision_normal = boundary.world_minimum_image(boundary_transform, contact1 - contact2).normalize();
+                let (vel1f, vel2f) = bounce_velocities(verlet1, verlet2, collision_normal);
+
+                verlet1.set_position(contact1);
+                verlet2.set_position(contact2);
+                if !verlet1.is_static() {
+                    let velocity = vel1f * coefficient_of_restitution;
+                    verlet1.set_position(contact1 + velocity * (1.0 - t) * dt);
+                    verlet1.set_velocity(velocity, dt);
+                }
+                if !verlet2.is_static() {
+                    let velocity = vel2f * coefficient_of_restitution;
+                    verlet2.set_position(contact2 + velocity * (1.0 - t) * dt);
+                    verlet2.set_velocity(velocity, dt);
+                }
+                continue;
+            }
 
-                verlet1.set_position(verlet1.get_position() + collision_normal * overlap / 2.0);
-                verlet2.set_position(verlet2.get_position() -  collision_normal * overlap / 2.0);
+            // Same idea against the container but from the inside - bounce at the wall and use up the rest of the step
+            let wall_velocity = self.boundary_transform.velocity_at(start);
+            if let Some((t, wall_normal)) = self.boundary.world_time_of_exit(&self.boundary_transform, start, motion - wall_velocity * dt, radius) {
+                let verlet = &mut self.verlets[i];
+                let contact = start + motion * t;
+                let vel = verlet.get_velocity() - wall_velocity;
+                let reflected = wall_velocity + bounce(vel, -wall_normal, wall_coefficient_of_restitution, self.wall_friction);
 
-                // keeping the perp vel same and changing the collision vel
-                verlet1.set_velocity((vel1_perp + vel1f) * coefficient_of_restitution, dt);
-                verlet2.set_velocity((vel2_perp + vel2f) * coefficient_of_restitution, dt);
+                let mut position = contact + reflected * (1.0 - t) * dt;
+                if let Some((correct_position, _)) = self.boundary.world_contact(&self.boundary_transform, position, radius) {
+                    position = correct_position;
+                }
+                verlet.set_position(position);
+                verlet.set_velocity(reflected, dt);
             }
         }
     }
 
+    #[allow(dead_code)]
     pub fn create_distance_constraint(&mut self, index1: usize, index2: usize, distance: f32) -> Result<(), String> {
         if index1 >= self.verlets.len() || index2 >= self.verlets.len() {
             return Err::<(), String>(String::from("Index out of bounds"));
         }
         self.constraints.push((index1.min(index2), index1.max(index2), distance));
+        self.constrained_pairs.insert((index1.min(index2), index1.max(index2)));
         Ok(())
     }
     pub fn create_distance_constraints(&mut self, contraints: &[(usize, usize, f32)]) -> Result<(), String> {
@@ -196,6 +1566,7 @@ impl Solver {
                 return Err::<(), String>(String::from("Index out of bounds"));
             }
             self.constraints.push((index1.min(index2), index1.max(index2), distance));
+            self.constrained_pairs.insert((index1.min(index2), index1.max(index2)));
         }
         Ok(())
     }
@@ -207,11 +1578,12 @@ impl Solver {
         let spring_dampening = 0.1 * self.contraint_spring_constant;
 
         for &(i, j, distance) in &self.constraints {
+            let dist_vec = self.displacement(self.verlets[i].get_position(), self.verlets[j].get_position());
+
             let (left, right) = self.verlets.split_at_mut(j);
             let verlet1 = &mut left[i];
             let verlet2 = &mut right[0];
 
-            let dist_vec = verlet2.get_position() - verlet1.get_position();
             let dist = dist_vec.length();
 
             let spring_force = dist_vec.normalize() * (dist - distance) * self.contraint_spring_constant;
@@ -225,6 +1597,212 @@ impl Solver {
         }
     }
 
+    // Stiffness is how much of the way to the rigid goal shape we move each substep - 1.0 is basically rigid
+    pub fn create_shape_match(&mut self, indices: &[usize], stiffness: f32) -> Result<usize, String> {
+        if indices.len() < 2 {
+            return Err::<usize, String>(String::from("Shape match needs at least 2 verlets"));
+        }
+        if indices.iter().any(|&index| index >= self.verlets.len()) {
+            return Err::<usize, String>(String::from("Index out of bounds"));
+        }
+        self.shape_matches.push(ShapeMatch::new(indices, &self.verlets, stiffness));
+        Ok(self.shape_matches.len() - 1)
+    }
+    #[allow(dead_code)]
+    pub fn get_shape_matches(&self) -> &Vec<ShapeMatch> {
+        &self.shape_matches
+    }
+    #[allow(dead_code)]
+    pub fn get_shape_matches_mut(&mut self) -> &mut Vec<ShapeMatch> {
+        &mut self.shape_matches
+    }
+
+    fn solve_shape_matches(&mut self) {
+        for shape_match in &self.shape_matches {
+            shape_match.solve(&mut self.verlets);
+        }
+    }
+
+    pub fn create_rigid_body(&mut self, indices: &[usize]) -> Result<usize, String> {
+        if indices.is_empty() {
+            return Err::<usize, String>(String::from("Rigid body needs at least 1 verlet"));
+        }
+        if indices.iter().any(|&index| index >= self.verlets.len()) {
+            return Err::<usize, String>(String::from("Index out of bounds"));
+        }
+        if indices.iter().any(|&index| self.verlets[index].get_rigid_body().is_some()) {
+            return Err::<usize, String>(String::from("Verlet already belongs to a rigid body"));
+        }
+
+        let id = self.rigid_bodies.len();
+        for &index in indices {
+            self.verlets[index].set_rigid_body(Some(id));
+        }
+        self.rigid_bodies.push(RigidBody::new(indices, &self.verlets));
+        Ok(id)
+    }
+    #[allow(dead_code)]
+    pub fn get_rigid_bodies(&self) -> &Vec<RigidBody> {
+        &self.rigid_bodies
+    }
+
+    fn solve_rigid_bodies(&mut self, dt: f32) {
+        for rigid_body in &mut self.rigid_bodies {
+            rigid_body.solve(&mut self.verlets, dt);
+        }
+    }
+
+    // Adds the particles and springs of a generated body in one block so it can be described with ranges
+    // Springs are pairs of local indices and get their rest length from the starting positions
+    fn add_body(&mut self, positions: &[Vec2], springs: &[(usize, usize)], options: &BodyOptions) -> Result<BodyHandle, String> {
+        if options.pinned.iter().any(|&local| local >= positions.len()) {
+            return Err::<BodyHandle, String>(String::from("Pinned index out of bounds"));
+        }
+
+        let verlet_start = self.verlets.len();
+        let mut particles: Vec<Verlet> = positions.iter()
+            .map(|&position| {
+                let mut particle = Verlet::new(position);
+                particle.set_radius(options.radius);
+                particle
+            })
+            .collect();
+        for particle in &mut particles {
+            particle.set_collision_group(options.collision_group);
+        }
+        for &local in &options.pinned {
+            particles[local].set_anchored(true);
+        }
+        self.add_positions(&mut particles);
+
+        let constraint_start = self.constraints.len();
+        let constraints: Vec<(usize, usize, f32)> = springs.iter()
+            .map(|&(a, b)| (verlet_start + a, verlet_start + b, (positions[a] - positions[b]).length()))
+            .collect();
+        self.create_distance_constraints(&constraints)?;
+
+        Ok(BodyHandle::new(verlet_start..self.verlets.len(), constraint_start..self.constraints.len()))
+    }
+
+    // Springs for a columns x rows lattice where local index = row * columns + column
+    fn lattice_springs(columns: usize, rows: usize, options: &BodyOptions) -> Vec<(usize, usize)> {
+        let mut springs = vec![];
+        for y in 0..rows {
+            for x in 0..columns {
+                let idx = y * columns + x;
+
+                // Structural
+                if x + 1 < columns {
+                    springs.push((idx, idx + 1));
+                }
+                if y + 1 < rows {
+                    springs.push((idx, idx + columns));
+                }
+
+                // Shear - the diagonals
+                if options.shear_springs && y + 1 < rows {
+                    if x + 1 < columns {
+                        springs.push((idx, idx + columns + 1));
+                    }
+                    if x > 0 {
+                        springs.push((idx, idx + columns - 1));
+                    }
+                }
+
+                // Bend - skip one particle
+                if options.bend_springs {
+                    if x + 2 < columns {
+                        springs.push((idx, idx + 2));
+                    }
+                    if y + 2 < rows {
+                        springs.push((idx, idx + 2 * columns));
+                    }
+                }
+            }
+        }
+        springs
+    }
+
+    // Cloth hangs down from top_left - local index = row * columns + column with row 0 at the top
+    pub fn create_cloth(&mut self, top_left: Vec2, columns: usize, rows: usize, options: &BodyOptions) -> Result<BodyHandle, String> {
+        if columns == 0 || rows == 0 {
+            return Err::<BodyHandle, String>(String::from("Cloth needs at least 1 column and 1 row"));
+        }
+
+        let mut positions = vec![];
+        for y in 0..rows {
+            for x in 0..columns {
+                positions.push(top_left + Vec2::new(x as f32, -(y as f32)) * options.spacing);
+            }
+        }
+
+        self.add_body(&positions, &Self::lattice_springs(columns, rows, options), options)
+    }
+
+    // Same lattice as the cloth but centered so it can be dropped as a solid block
+    pub fn create_box(&mut self, center: Vec2, columns: usize, rows: usize, options: &BodyOptions) -> Result<BodyHandle, String> {
+        if columns == 0 || rows == 0 {
+            return Err::<BodyHandle, String>(String::from("Box needs at least 1 column and 1 row"));
+        }
+
+        let size = Vec2::new((columns - 1) as f32, (rows - 1) as f32) * options.spacing;
+        self.create_cloth(center + Vec2::new(-size.x, size.y) / 2.0, columns, rows, options)
+    }
+
+    // Spacing is ignored since the segments are evenly spread between start and end
+    pub fn create_rope(&mut self, start: Vec2, end: Vec2, segments: usize, options: &BodyOptions) -> Result<BodyHandle, String> {
+        if segments == 0 {
+            return Err::<BodyHandle, String>(String::from("Rope needs at least 1 segment"));
+        }
+
+        let positions: Vec<Vec2> = (0..=segments)
+            .map(|i| start.lerp(end, i as f32 / segments as f32))
+            .collect();
+
+        let mut springs = vec![];
+        for i in 0..segments {
+            springs.push((i, i + 1));
+            if options.bend_springs && i + 2 <= segments {
+                springs.push((i, i + 2));
+            }
+        }
+
+        self.add_body(&positions, &springs, options)
+    }
+
+    // A wheel - local index 0 is the hub and 1..=segments go around the rim
+    // Spokes always connect the hub, shear springs brace every rim segment with the next spoke and bend springs skip a rim particle
+    pub fn create_circle(&mut self, center: Vec2, radius: f32, segments: usize, options: &BodyOptions) -> Result<BodyHandle, String> {
+        if segments < 3 {
+            return Err::<BodyHandle, String>(String::from("Circle needs at least 3 segments"));
+        }
+
+        let mut positions = vec![center];
+        for i in 0..segments {
+            let angle = i as f32 / segments as f32 * std::f32::consts::TAU;
+            positions.push(center + Vec2::from_angle(angle) * radius);
+        }
+
+        let rim = |i: usize| 1 + i % segments;
+        // Skipping halfway around reaches the same pair from both sides so only keep the first half of those
+        let has_rim_spring = |i: usize, skip: usize| 2 * skip < segments || (2 * skip == segments && i < skip);
+
+        let mut springs = vec![];
+        for i in 0..segments {
+            springs.push((0, rim(i)));
+            springs.push((rim(i), rim(i + 1)));
+            if options.shear_springs && has_rim_spring(i, 2) {
+                springs.push((rim(i), rim(i + 2)));
+            }
+            if options.bend_springs && has_rim_spring(i, 3) {
+                springs.push((rim(i), rim(i + 3)));
+            }
+        }
+
+        self.add_body(&positions, &springs, options)
+    }
+
+    #[allow(dead_code)]
     pub fn is_container_full(&self) -> bool {
         // Calculate total area of particles
         let total_particle_area: f32 = self.verlets
@@ -233,7 +1811,7 @@ impl Solver {
             .sum();
         
         // Calculate container area
-        let container_area = std::f32::consts::PI * self.constraint_radius * self.constraint_radius;
+        let container_area = self.boundary.area();
         
         // Consider it full if particles take up more than X% of space
         // Note: Perfect circle packing is ~90.7% efficient
@@ -251,12 +1829,13 @@ impl Solver {
         let sigma = 10.0;
         let kernel = self.create_gaussian_kernel(kernel_size, sigma);
         
+        let (min, max) = self.boundary.world_aabb(&self.boundary_transform);
         for verlet in &mut self.verlets {
             let pos: Vec2 = verlet.get_position();
             
-            // Map position to image coordinates
-            let x_ratio = 1.0 - ((pos.x / self.constraint_radius) + 1.0) * 0.5;
-            let y_ratio = 1.0 - ((pos.y / self.constraint_radius) + 1.0) * 0.5;
+            // Map position to image coordinates - the image is stretched over the boundary's bounding box
+            let x_ratio = 1.0 - (pos.x - min.x) / (max.x - min.x);
+            let y_ratio = 1.0 - (pos.y - min.y) / (max.y - min.y);
             
             let img_x = (x_ratio * (img_width - 1) as f32) as i32;
             let img_y = (y_ratio * (img_height - 1) as f32) as i32;
@@ -316,12 +1895,12 @@ impl Solver {
         let mut kernel = vec![vec![0.0; size]; size];
         let center = (size as f32 - 1.0) / 2.0;
         
-        for y in 0..size {
-            for x in 0..size {
+        for (y, row) in kernel.iter_mut().enumerate() {
+            for (x, value) in row.iter_mut().enumerate() {
                 let dx = x as f32 - center;
                 let dy = y as f32 - center;
                 let exponent = -(dx * dx + dy * dy) / (2.0 * sigma * sigma);
-                kernel[y][x] = 1.0 / (2.0 * std::f32::consts::PI * sigma * sigma) * exponent.exp();
+                *value = 1.0 / (2.0 * std::f32::consts::PI * sigma * sigma) * exponent.exp();
             }
         }
         
@@ -360,6 +1939,7 @@ impl Solver {
         Ok(())
     }
 
+    #[allow(dead_code)]
     pub fn get_positions(&self) -> Vec<Vec2> {
         self.verlets.iter()
             .map(|verlet| verlet.get_position())
@@ -370,6 +1950,7 @@ impl Solver {
             verlet.set_color(self.color_frames[self.current_frame]);
             self.current_frame += 1;
         }
+        start_at_ambient(&mut verlet, self.thermal.as_ref());
         self.verlets.push(verlet);
     }
     pub fn add_positions(&mut self, verlets: &mut [Verlet]) {
@@ -378,14 +1959,752 @@ impl Solver {
                 verlet.set_color(self.color_frames[self.current_frame]);
                 self.current_frame += 1;
             }
+            start_at_ambient(verlet, self.thermal.as_ref());
         }
         self.verlets.extend(verlets.iter().cloned());
     }
 
+    // Takes the verlets out and fixes up every index into the list that's left
+    // Constraints using a removed verlet go, shape matches and rigid bodies lose it and go when there's not enough left
+    // BodyHandles from before this point at the wrong verlets afterwards
+    pub fn remove_verlets(&mut self, indices: &[usize]) -> Result<(), String> {
+        if indices.iter().any(|&index| index >= self.verlets.len()) {
+            return Err::<(), String>(String::from("Index out of bounds"));
+        }
+
+        let mut keep = vec![true; self.verlets.len()];
+        for &index in indices {
+            keep[index] = false;
+        }
+        self.retain_verlets(&keep);
+        Ok(())
+    }
+
+    // Where every verlet index from the last call ended up after merges and removals shifted them down - None for ones that are gone
+    // Lets anything outside the solver that holds on to indices follow them - there should only be one thing calling it
+    pub fn take_index_remap(&mut self) -> Option<Vec<Option<usize>>> {
+        if self.index_remap.is_empty() {
+            return None;
+        }
+        Some(std::mem::take(&mut self.index_remap))
+    }
+
+    fn retain_verlets(&mut self, keep: &[bool]) {
+        let mut remap = vec![None; keep.len()];
+        let mut next = 0;
+        for (i, &kept) in keep.iter().enumerate() {
+            if kept {
+                remap[i] = Some(next);
+                next += 1;
+            }
+        }
+        if next == keep.len() {
+            return;
+        }
+
+        // Stacked onto the removals before so take_index_remap goes straight from the old indices to the new ones
+        if self.index_remap.is_empty() {
+            self.index_remap = remap.clone();
+        } else {
+            for index in &mut self.index_remap {
+                *index = index.and_then(|index| remap[index]);
+            }
+        }
+
+        let removed: Vec<(Vec2, f32)> = self.verlets.iter().zip(keep)
+            .filter(|(_, kept)| !**kept)
+            .map(|(verlet, _)| (verlet.get_position(), verlet.get_radius()))
+            .collect();
+
+        let mut index = 0;
+        self.verlets.retain(|_| {
+            index += 1;
+            keep[index - 1]
+        });
+        if self.fluid_densities.len() == keep.len() {
+            let mut index = 0;
+            self.fluid_densities.retain(|_| {
+                index += 1;
+                keep[index - 1]
+            });
+        }
+
+        self.constraints = self.constraints.iter()
+            .filter_map(|&(i, j, distance)| Some((remap[i]?, remap[j]?, distance)))
+            .collect();
+        self.constrained_pairs = self.constrained_pairs.iter()
+            .filter_map(|&(i, j)| Some((remap[i]?, remap[j]?)))
+            .collect();
+
+        for shape_match in &mut self.shape_matches {
+            shape_match.remap(&remap, &self.verlets);
+        }
+        self.shape_matches.retain(|shape_match| shape_match.get_indices().len() >= 2);
+
+        // Rigid bodies that end up empty go too so the ones after them move down and every verlet's body id has to follow
+        let mut body_remap = vec![None; self.rigid_bodies.len()];
+        let mut next_body = 0;
+        for (body, rigid_body) in self.rigid_bodies.iter_mut().enumerate() {
+            rigid_body.remap(&remap, &self.verlets);
+            if !rigid_body.get_indices().is_empty() {
+                body_remap[body] = Some(next_body);
+                next_body += 1;
+            }
+        }
+        self.rigid_bodies.retain(|rigid_body| !rigid_body.get_indices().is_empty());
+        for verlet in &mut self.verlets {
+            if let Some(body) = verlet.get_rigid_body() {
+                verlet.set_rigid_body(body_remap[body]);
+            }
+        }
+
+        self.wake_around(&removed);
+    }
+
+    // Anything asleep within a diameter of a removed verlet might have been resting on it - its island wakes with it next substep
+    fn wake_around(&mut self, removed: &[(Vec2, f32)]) {
+        let to_wake: Vec<usize> = (0..self.verlets.len())
+            .filter(|&i| self.verlets[i].is_sleeping())
+            .filter(|&i| {
+                let (position, radius) = (self.verlets[i].get_position(), self.verlets[i].get_radius());
+                removed.iter().any(|&(removed_position, removed_radius)| {
+                    self.displacement(removed_position, position).length() < radius + removed_radius * 3.0
+                })
+            })
+            .collect();
+        for i in to_wake {
+            self.verlets[i].set_sleeping(false);
+        }
+    }
+
     pub fn get_verlets(&self) -> &Vec<Verlet> {
         &self.verlets
     }
     pub fn get_verlets_mut(&mut self) -> &mut Vec<Verlet> {
         &mut self.verlets
     }
-}
\ No newline at end of file
+}
+
+// Elastic bounce along the collision normal - keeping the perp vel same and changing the collision vel
+fn bounce_velocities(verlet1: &Verlet, verlet2: &Verlet, collision_normal: Vec2) -> (Vec2, Vec2) {
+    let collision_perp_normal = collision_normal.perp();
+
+    let vel1 = verlet1.get_velocity().project_onto(collision_normal);
+    let vel1_perp = verlet1.get_velocity().project_onto(collision_perp_normal);
+    let vel2 = verlet2.get_velocity().project_onto(collision_normal);
+    let vel2_perp = verlet2.get_velocity().project_onto(collision_perp_normal);
+    let m1 = verlet1.get_mass();
+    let m2 = verlet2.get_mass();
+
+    // Anchored or sleeping is like a wall with infinite mass
+    let (vel1f, vel2f) = if verlet1.is_static() {
+        (vel1, 2.0 * vel1 - vel2)
+    } else if verlet2.is_static() {
+        (2.0 * vel2 - vel1, vel2)
+    } else {
+        (
+            (vel1 * (m1 - m2) + 2.0 * m2 *  vel2) / (m1 + m2),
+            (vel2 * (m2 - m1) + 2.0 * m1 *  vel1) / (m1 + m2),
+        )
+    };
+
+    (vel1_perp + vel1f, vel2_perp + vel2f)
+}
+
+fn start_at_ambient(verlet: &mut Verlet, thermal: Option<&Thermal>) {
+    if let Some(thermal) = thermal && !verlet.has_temperature() {
+        verlet.set_temperature(thermal.ambient_temperature);
+    }
+}
+
+// Something awake hitting a sleeping verlet faster than sleep_speed wakes it up
+// Slower touches leave it asleep so the awake one just rests on it like on a wall
+fn wake_on_contact(verlet1: &mut Verlet, verlet2: &mut Verlet, sleep_speed: f32) {
+    if verlet1.is_sleeping() && !verlet2.is_static() && verlet2.get_velocity().length() > sleep_speed {
+        verlet1.set_sleeping(false);
+    }
+    if verlet2.is_sleeping() && !verlet1.is_static() && verlet1.get_velocity().length() > sleep_speed {
+        verlet2.set_sleeping(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec2;
+    use super::*;
+    use super::super::force_field::RadialGravity;
+
+    // Rows of touching balls on the floor of a box - left to settle and fall asleep
+    fn sleeping_pile(columns: usize, rows: usize) -> Solver {
+        let mut solver = Solver::new(&[], vec2(0.0, -100.0), 200.0, 8, 25.0, 10000.0);
+        solver.set_boundary(Boundary::Box { min: vec2(-100.0, -100.0), max: vec2(100.0, 100.0) });
+        solver.set_sleeping_enabled(true, 5.0, 0.5);
+        for y in 0..rows {
+            for x in 0..columns {
+                let mut verlet = Verlet::new(vec2(x as f32 * 20.0 - 90.0, y as f32 * 20.0 - 90.0));
+                verlet.set_radius(10.0);
+                solver.add_position(verlet);
+            }
+        }
+        for _ in 0..180 {
+            solver.update(1.0 / 60.0);
+        }
+        solver
+    }
+
+    // Lowest each verlet gets over the next second - the walls bounce perfectly so where they end up says nothing
+    fn lowest_over_a_second(solver: &mut Solver) -> Vec<f32> {
+        let mut lowest: Vec<f32> = solver.get_verlets().iter().map(|verlet| verlet.get_position().y).collect();
+        for _ in 0..60 {
+            solver.update(1.0 / 60.0);
+            for (lowest, verlet) in lowest.iter_mut().zip(solver.get_verlets()) {
+                *lowest = lowest.min(verlet.get_position().y);
+            }
+        }
+        lowest
+    }
+
+    #[test]
+    fn resting_pile_stays_asleep() {
+        let mut solver = sleeping_pile(10, 3);
+        assert_eq!(solver.get_sleeping_count(), 30);
+        for _ in 0..60 {
+            solver.update(1.0 / 60.0);
+            assert_eq!(solver.get_sleeping_count(), 30);
+        }
+    }
+
+    #[test]
+    fn removing_the_bottom_row_drops_the_rest() {
+        let mut solver = sleeping_pile(10, 3);
+        let bottom: Vec<usize> = (0..solver.get_verlets().len())
+            .filter(|&i| solver.get_verlets()[i].get_position().y < -85.0)
+            .collect();
+        solver.remove_verlets(&bottom).unwrap();
+        assert_eq!(solver.get_verlets().len(), 20);
+
+        // Both rows that were left fall the 20 the bottom row held them up
+        let before: Vec<f32> = solver.get_verlets().iter().map(|verlet| verlet.get_position().y).collect();
+        for (lowest, before) in lowest_over_a_second(&mut solver).iter().zip(before) {
+            assert!(*lowest < before - 15.0, "{lowest} from {before}");
+        }
+    }
+
+    #[test]
+    fn moving_the_floor_away_wakes_the_pile() {
+        let mut solver = sleeping_pile(10, 1);
+        assert_eq!(solver.get_sleeping_count(), 10);
+        solver.set_boundary(Boundary::Box { min: vec2(-100.0, -150.0), max: vec2(100.0, 100.0) });
+        assert!(lowest_over_a_second(&mut solver).iter().all(|&lowest| lowest < -139.0));
+    }
+
+    #[test]
+    fn removing_a_collider_drops_what_was_on_it() {
+        let mut solver = Solver::new(&[], vec2(0.0, -100.0), 200.0, 8, 25.0, 10000.0);
+        solver.set_boundary(Boundary::Box { min: vec2(-100.0, -100.0), max: vec2(100.0, 100.0) });
+        solver.set_sleeping_enabled(true, 5.0, 0.5);
+        solver.add_collider(Collider::new(ColliderShape::Segment { start: vec2(-100.0, 0.0), end: vec2(100.0, 0.0) }, 0.0, 0.5));
+        let mut verlet = Verlet::new(vec2(0.0, 20.0));
+        verlet.set_radius(10.0);
+        solver.add_position(verlet);
+        for _ in 0..180 {
+            solver.update(1.0 / 60.0);
+        }
+        assert_eq!(solver.get_sleeping_count(), 1);
+
+        solver.remove_collider(0).unwrap();
+        // Falls 50 in a second
+        assert!(lowest_over_a_second(&mut solver)[0] < -35.0);
+    }
+
+    #[test]
+    fn untracked_verlets_start_at_ambient() {
+        let mut solver = sleeping_pile(2, 1);
+        solver.get_verlets_mut()[1].set_temperature(80.0);
+        solver.set_thermal(Some(Thermal::new(1.0, 20.0, 0.1)));
+        assert_eq!(solver.get_verlets()[0].get_temperature(), 20.0);
+        assert_eq!(solver.get_verlets()[1].get_temperature(), 80.0);
+
+        solver.add_position(Verlet::new(vec2(0.0, 50.0)));
+        assert_eq!(solver.get_verlets()[2].get_temperature(), 20.0);
+    }
+
+    #[test]
+    fn heat_wakes_a_sleeping_pile_and_spreads_through_it() {
+        let mut solver = sleeping_pile(10, 3);
+        assert_eq!(solver.get_sleeping_count(), 30);
+
+        // Too little expansion to ever float so only waking on heat gets it past the bottom row
+        let mut thermal = Thermal::new(3.0, 0.0, 0.001);
+        thermal.add_wall_heat(vec2(0.0, -1.0), 50.0, 20.0, 5.0);
+        solver.set_thermal(Some(thermal));
+        for _ in 0..300 {
+            solver.update(1.0 / 60.0);
+        }
+        let top_row = solver.get_verlets().iter().filter(|verlet| verlet.get_position().y > -60.0);
+        assert!(top_row.clone().count() > 0);
+        assert!(top_row.clone().all(|verlet| verlet.get_temperature() > 5.0));
+    }
+
+    #[test]
+    fn two_cell_periodic_grid_finds_each_pair_once() {
+        let mut solver = Solver::new(&[], Vec2::ZERO, 200.0, 8, 20.0, 10000.0);
+        solver.set_boundary(Boundary::Periodic { min: vec2(-20.0, -20.0), max: vec2(20.0, 20.0) });
+        // Across the middle line and across the wrap
+        for position in [vec2(-3.0, 5.0), vec2(3.0, 5.0), vec2(-18.0, -5.0), vec2(18.0, -5.0)] {
+            let mut verlet = Verlet::new(position);
+            verlet.set_radius(5.0);
+            solver.add_position(verlet);
+        }
+        solver.resize_grid();
+        assert_eq!((solver.grid_width, solver.grid_height), (2, 2));
+
+        let mut collisions = solver.find_collisions_space_partitioning();
+        collisions.sort();
+        let mut unique = collisions.clone();
+        unique.dedup();
+        assert_eq!(collisions, unique);
+        assert!(collisions.contains(&(0, 1)) && collisions.contains(&(2, 3)));
+
+        let mut pairs = solver.find_pairs_within(10.0, |_| true);
+        pairs.sort();
+        assert_eq!(pairs, vec![(0, 1), (2, 3)]);
+    }
+
+    #[test]
+    fn moving_boundary_leaves_the_collider_grid_alone() {
+        let mut solver = Solver::new(&[], vec2(0.0, -100.0), 200.0, 8, 25.0, 10000.0);
+        solver.set_boundary(Boundary::Box { min: vec2(-100.0, -100.0), max: vec2(100.0, 100.0) });
+        solver.add_collider(Collider::new(ColliderShape::Segment { start: vec2(-50.0, 0.0), end: vec2(50.0, 0.0) }, 0.0, 0.5));
+        let mut verlet = Verlet::new(vec2(0.0, 20.0));
+        verlet.set_radius(10.0);
+        solver.add_position(verlet);
+        let collider_grid = solver.collider_grid.clone();
+
+        // Slides the box along until the segment is nowhere near its middle
+        solver.set_boundary_velocity(vec2(60.0, 0.0), 0.0);
+        for _ in 0..60 {
+            solver.update(1.0 / 60.0);
+        }
+        assert_eq!(solver.collider_grid, collider_grid);
+        assert_eq!(solver.collider_grid_origin, vec2(-50.0, 0.0));
+        // Still sitting on the segment
+        assert!(solver.get_verlets()[0].get_position().y > 5.0);
+
+        let mut nearby = vec![];
+        solver.colliders_near(vec2(200.0, 0.0), 10.0, &mut nearby);
+        assert!(nearby.is_empty());
+        solver.colliders_near(vec2(0.0, 5.0), 10.0, &mut nearby);
+        assert_eq!(nearby, vec![0]);
+    }
+
+    #[test]
+    fn continuous_collision_uses_up_the_rest_of_the_substep() {
+        let mut bullet = Verlet::new(vec2(-50.0, 0.0));
+        bullet.set_radius(5.0);
+        bullet.set_continuous_collision(true);
+        let mut wall = Verlet::new(Vec2::ZERO);
+        wall.set_radius(5.0);
+        wall.set_anchored(true);
+        let mut solver = Solver::new(&[bullet, wall], Vec2::ZERO, 200.0, 8, 25.0, 10000.0);
+        // 25 a substep so the second one goes right through the wall and touches it 60% of the way along
+        solver.get_verlets_mut()[0].set_velocity(vec2(12000.0, 0.0), 1.0 / 480.0);
+        solver.update_with_subdivision(1.0 / 240.0, 2);
+
+        let bullet = &solver.get_verlets()[0];
+        assert!(bullet.get_velocity().x < 0.0);
+        // Bounced at -10 then went back the other 40% at the bounced speed
+        let expected = -10.0 + bullet.get_velocity().x * 0.4 / 480.0;
+        assert!((bullet.get_position().x - expected).abs() < 0.5, "{} {}", bullet.get_position().x, expected);
+        assert!(expected < -15.0);
+    }
+
+    fn total_momentum(solver: &Solver) -> Vec2 {
+        solver.get_verlets().iter().map(|verlet| verlet.get_velocity() * verlet.get_mass()).sum()
+    }
+
+    fn total_mass(solver: &Solver) -> f32 {
+        solver.get_verlets().iter().map(|verlet| verlet.get_mass()).sum()
+    }
+
+    #[test]
+    fn merging_keeps_mass_and_momentum() {
+        let mut solver = Solver::new(&[], Vec2::ZERO, 200.0, 8, 25.0, 10000.0);
+        solver.set_merge_rules(Some(MergeRules::new(f32::INFINITY, f32::INFINITY)));
+        for (position, radius, velocity) in [(vec2(-10.0, 0.0), 10.0, vec2(30.0, 5.0)), (vec2(5.0, 0.0), 5.0, vec2(-60.0, 20.0))] {
+            let mut verlet = Verlet::new(position);
+            verlet.set_radius(radius);
+            verlet.set_velocity(velocity, 1.0 / 60.0);
+            solver.add_position(verlet);
+        }
+        let (mass, momentum) = (total_mass(&solver), total_momentum(&solver));
+        let center_of_mass: Vec2 = solver.get_verlets().iter().map(|verlet| verlet.get_position() * verlet.get_mass()).sum::<Vec2>() / mass;
+
+        solver.merge_and_split(&[(0, 1)], &[], 1.0 / 60.0);
+        assert_eq!(solver.get_verlets().len(), 1);
+        let merged = &solver.get_verlets()[0];
+        assert!((merged.get_mass() - mass).abs() < 1e-3 * mass);
+        assert!((total_momentum(&solver) - momentum).length() < 1e-3 * momentum.length());
+        assert!((merged.get_position() - center_of_mass).length() < 1e-4);
+    }
+
+    #[test]
+    fn split_pieces_stay_inside_the_old_circle() {
+        let mut solver = Solver::new(&[], Vec2::ZERO, 200.0, 8, 25.0, 10000.0);
+        let mut rules = MergeRules::new(0.0, 0.0);
+        rules.fragments = 4;
+        solver.set_merge_rules(Some(rules));
+        let mut verlet = Verlet::new(vec2(20.0, 10.0));
+        verlet.set_radius(12.0);
+        verlet.set_velocity(vec2(-40.0, 15.0), 1.0 / 60.0);
+        solver.add_position(verlet);
+        let (mass, momentum) = (total_mass(&solver), total_momentum(&solver));
+
+        solver.merge_and_split(&[], &[(0, 100.0)], 1.0 / 60.0);
+        assert_eq!(solver.get_verlets().len(), 4);
+        for piece in solver.get_verlets() {
+            assert!(piece.get_position().distance(vec2(20.0, 10.0)) + piece.get_radius() <= 12.0 + 1e-4);
+        }
+        assert!((total_mass(&solver) - mass).abs() < 1e-3 * mass);
+        assert!((total_momentum(&solver) - momentum).length() < 1e-3 * momentum.length());
+    }
+
+    #[test]
+    fn removing_verlets_remaps_what_comes_after() {
+        let mut solver = sleeping_pile(5, 1);
+        let positions: Vec<Vec2> = solver.get_verlets().iter().map(|verlet| verlet.get_position()).collect();
+        solver.take_index_remap();
+
+        solver.remove_verlets(&[1, 3]).unwrap();
+        let remap = solver.take_index_remap().unwrap();
+        assert_eq!(remap, vec![Some(0), None, Some(1), None, Some(2)]);
+        for (old, new) in remap.iter().enumerate() {
+            if let Some(new) = new {
+                assert_eq!(solver.get_verlets()[*new].get_position(), positions[old]);
+            }
+        }
+
+        // A second removal composes with the first until someone takes it
+        solver.remove_verlets(&[0]).unwrap();
+        solver.remove_verlets(&[0]).unwrap();
+        assert_eq!(solver.take_index_remap().unwrap(), vec![None, None, Some(0)]);
+        assert_eq!(solver.get_verlets()[0].get_position(), positions[4]);
+        assert_eq!(solver.take_index_remap(), None);
+    }
+
+    #[test]
+    fn sleeping_without_colliders_near_the_origin() {
+        let mut solver = Solver::new(&[], vec2(0.0, -100.0), 200.0, 8, 25.0, 10000.0);
+        solver.set_boundary(Boundary::Box { min: vec2(-100.0, -15.0), max: vec2(100.0, 100.0) });
+        solver.set_sleeping_enabled(true, 5.0, 0.5);
+        for position in [vec2(0.0, -10.0), vec2(0.0, 0.0)] {
+            let mut verlet = Verlet::new(position);
+            verlet.set_radius(5.0);
+            solver.add_position(verlet);
+        }
+        for _ in 0..120 {
+            solver.update(1.0 / 60.0);
+        }
+        assert_eq!(solver.get_sleeping_count(), 2);
+
+        let mut nearby = vec![3];
+        solver.colliders_near(Vec2::ZERO, 10.0, &mut nearby);
+        assert!(nearby.is_empty());
+    }
+
+    #[test]
+    fn removing_the_last_collider() {
+        let mut solver = Solver::new(&[], vec2(0.0, -100.0), 200.0, 8, 25.0, 10000.0);
+        solver.add_collider(Collider::new(ColliderShape::Segment { start: vec2(-50.0, 0.0), end: vec2(50.0, 0.0) }, 0.0, 0.5));
+        let mut verlet = Verlet::new(vec2(0.0, 20.0));
+        verlet.set_radius(10.0);
+        solver.add_position(verlet);
+        solver.remove_collider(0).unwrap();
+        assert!(solver.get_colliders().is_empty());
+        assert!(solver.remove_collider(0).is_err());
+
+        let mut nearby = vec![];
+        solver.colliders_near(Vec2::ZERO, 10.0, &mut nearby);
+        assert!(nearby.is_empty());
+        // Falls straight through where the segment was
+        assert!(lowest_over_a_second(&mut solver)[0] < -10.0);
+    }
+
+    #[test]
+    fn sleeping_pile_makes_no_pairs() {
+        let mut solver = sleeping_pile(10, 3);
+        assert_eq!(solver.get_sleeping_count(), 30);
+        assert!(solver.find_collisions_space_partitioning().is_empty());
+        assert!(solver.sleepers_near_moving(&[]).is_empty());
+    }
+
+    #[test]
+    fn dragging_the_support_out_drops_what_was_on_it() {
+        let mut solver = sleeping_pile(1, 2);
+        assert_eq!(solver.get_sleeping_count(), 2);
+        let top_start = solver.get_verlets()[1].get_position().y;
+
+        // Slid out slower than sleep_speed so it never knocks the top one awake - only losing the support can
+        let mut lowest = top_start;
+        for _ in 0..360 {
+            let bottom = &mut solver.get_verlets_mut()[0];
+            bottom.set_sleeping(false);
+            bottom.set_velocity(vec2(4.0, 0.0), 1.0 / 60.0);
+            solver.update(1.0 / 60.0);
+            lowest = lowest.min(solver.get_verlets()[1].get_position().y);
+        }
+        assert!(lowest < top_start - 15.0, "{lowest} {top_start}");
+    }
+
+    #[test]
+    fn turning_gravity_around_wakes_the_pile() {
+        let mut solver = sleeping_pile(10, 1);
+        solver.set_gravity(vec2(0.0, 100.0));
+        assert_eq!(solver.get_sleeping_count(), 0);
+        solver.update(1.0 / 60.0);
+        assert!(solver.get_verlets().iter().all(|verlet| verlet.get_velocity().y > 0.0));
+    }
+
+    // Energy per unit mass of something orbiting a RadialGravity with the given strength at the origin
+    fn orbit_energy(verlet: &Verlet, strength: f32) -> f32 {
+        0.5 * verlet.get_velocity().length_squared() - strength / verlet.get_position().length()
+    }
+
+    #[test]
+    fn integrators_keep_an_orbit_together() {
+        let strength = 1.0e6;
+        let mut drifts = vec![];
+        for integrator in [Integrator::PositionVerlet, Integrator::VelocityVerlet, Integrator::SemiImplicitEuler, Integrator::Rk4] {
+            // One step a frame so the difference between them shows up over float error
+            let mut solver = Solver::new(&[], Vec2::ZERO, 200.0, 1, 25.0, 10000.0);
+            solver.set_integrator(integrator);
+            solver.add_force_field(Box::new(RadialGravity::new(Vec2::ZERO, strength, 0.0)));
+            // Circular at 100 out which goes around about every 6 seconds
+            let mut verlet = Verlet::new(vec2(100.0, 0.0));
+            verlet.set_velocity(vec2(0.0, (strength / 100.0f32).sqrt()), 1.0 / 60.0);
+            solver.add_position(verlet);
+
+            let start = orbit_energy(&solver.get_verlets()[0], strength);
+            let mut drift: f32 = 0.0;
+            for _ in 0..1200 {
+                solver.update(1.0 / 60.0);
+                drift = drift.max(((orbit_energy(&solver.get_verlets()[0], strength) - start) / start).abs());
+            }
+            let radius = solver.get_verlets()[0].get_position().length();
+            assert!((radius - 100.0).abs() < 1.0, "{integrator:?} ended up {radius} out");
+            drifts.push(drift);
+        }
+
+        // Every one holds on over 3 orbits - velocity verlet and RK4 track the energy a lot closer than semi-implicit Euler's first order velocity
+        assert!(drifts.iter().all(|&drift| drift < 1e-3), "{drifts:?}");
+        assert!(drifts[1] < drifts[2] * 0.5 && drifts[3] < drifts[2] * 0.5, "{drifts:?}");
+    }
+
+    #[test]
+    fn changing_substeps_mid_flight_keeps_the_velocity() {
+        let gravity = vec2(0.0, -100.0);
+        let mut solver = Solver::new(&[], gravity, 200.0, 8, 25.0, 10000.0);
+        let start_velocity = vec2(60.0, 80.0);
+        let mut verlet = Verlet::new(vec2(-100.0, -100.0));
+        verlet.set_velocity(start_velocity, 1.0 / 480.0);
+        solver.add_position(verlet);
+        let energy = |verlet: &Verlet| 0.5 * verlet.get_velocity().length_squared() - gravity.dot(verlet.get_position());
+        let start_energy = energy(&solver.get_verlets()[0]);
+
+        // 8 then 3 then 12 substeps a frame
+        let mut time = 0.0;
+        for subdivision in [8, 3, 12] {
+            solver.set_subdivision(subdivision);
+            for _ in 0..20 {
+                solver.update(1.0 / 60.0);
+                time += 1.0 / 60.0;
+                let verlet = &solver.get_verlets()[0];
+                let expected = start_velocity + gravity * time;
+                assert!((verlet.get_velocity() - expected).length() < 1.0, "{} vs {expected} with {subdivision}", verlet.get_velocity());
+                assert!((energy(verlet) - start_energy).abs() < 0.01 * start_energy.abs(), "{} vs {start_energy}", energy(verlet));
+            }
+        }
+    }
+
+    #[test]
+    fn adaptive_substeps_follow_the_fastest_verlet() {
+        let mut solver = Solver::new(&[], vec2(0.0, -100.0), 400.0, 8, 25.0, 10000.0);
+        solver.set_adaptive_subdivision(true, 32, 0.5);
+        assert!(solver.is_adaptive_subdivision());
+        let mut verlet = Verlet::new(vec2(0.0, 0.0));
+        verlet.set_velocity(vec2(3000.0, 0.0), 1.0 / 480.0);
+        solver.add_position(verlet);
+
+        // 50 units a frame on a radius of 9 is about 12 substeps at half a radius each
+        solver.update(1.0 / 60.0);
+        let fast = solver.get_last_subdivision();
+        assert!(fast > 8 && fast <= 32, "{fast}");
+
+        solver.get_verlets_mut()[0].set_velocity(vec2(0.0, 0.0), 1.0 / 60.0 / fast as f32);
+        solver.update(1.0 / 60.0);
+        assert_eq!(solver.get_last_subdivision(), 1);
+
+        // Turned off it's back to the fixed count
+        solver.set_adaptive_subdivision(false, 32, 0.5);
+        solver.update(1.0 / 60.0);
+        assert_eq!(solver.get_last_subdivision(), 8);
+    }
+
+    #[test]
+    fn ghosts_never_get_hit() {
+        let mut ghost = Verlet::new(vec2(0.0, 0.0));
+        ghost.set_collision_layer(2);
+        ghost.set_collision_mask(0);
+        let plain = Verlet::new(vec2(1.0, 0.0));
+        let mut everything = Verlet::new(vec2(-1.0, 0.0));
+        everything.set_collision_layer(u32::MAX);
+        everything.set_collision_mask(u32::MAX);
+        let solver = Solver::new(&[ghost, plain, everything], vec2(0.0, -100.0), 200.0, 8, 25.0, 10000.0);
+
+        // Hitting everything still needs the ghost to want to be hit back
+        for other in [1, 2] {
+            assert!(!solver.can_collide(0, other));
+            assert!(!solver.can_collide(other, 0));
+        }
+        assert!(solver.can_collide(1, 2));
+    }
+
+    fn body_springs<'a>(solver: &'a Solver, body: &BodyHandle) -> &'a [(usize, usize, f32)] {
+        &solver.get_contraints()[body.get_constraints()]
+    }
+
+    #[test]
+    fn box_is_a_braced_lattice_around_its_center() {
+        let mut solver = Solver::new(&[], vec2(0.0, -100.0), 400.0, 8, 25.0, 10000.0);
+        let options = BodyOptions { spacing: 10.0, ..BodyOptions::default() };
+        let body = solver.create_box(vec2(50.0, 20.0), 4, 3, &options).unwrap();
+
+        assert_eq!(body.len(), 12);
+        let center = solver.get_verlets()[body.get_verlets()].iter().map(|verlet| verlet.get_position()).sum::<Vec2>() / 12.0;
+        assert!((center - vec2(50.0, 20.0)).length() < 1e-4, "{center}");
+
+        // 3 * 3 + 4 * 2 structural and two diagonals in each of the 3 * 2 squares
+        let springs = body_springs(&solver, &body);
+        assert_eq!(springs.len(), 17 + 12);
+        assert_eq!(springs.iter().filter(|spring| (spring.2 - 10.0).abs() < 1e-4).count(), 17);
+        assert_eq!(springs.iter().filter(|spring| (spring.2 - 10.0 * 2.0_f32.sqrt()).abs() < 1e-3).count(), 12);
+
+        assert!(solver.create_box(vec2(0.0, 0.0), 0, 3, &options).is_err());
+    }
+
+    #[test]
+    fn rope_springs_split_it_evenly() {
+        let mut solver = Solver::new(&[], vec2(0.0, -100.0), 400.0, 8, 25.0, 10000.0);
+        let options = BodyOptions { bend_springs: true, ..BodyOptions::default() };
+        let body = solver.create_rope(vec2(0.0, 0.0), vec2(30.0, 40.0), 5, &options).unwrap();
+
+        assert_eq!(body.len(), 6);
+        let springs = body_springs(&solver, &body);
+        assert_eq!(springs.len(), 5 + 4);
+        for &(a, b, rest_length) in springs {
+            // Neighbours are a tenth of the 50 long rope apart and bend springs skip one
+            let expected = 10.0 * (b - a) as f32;
+            assert!((rest_length - expected).abs() < 1e-4, "{a} {b} {rest_length}");
+        }
+
+        assert!(solver.create_rope(vec2(0.0, 0.0), vec2(1.0, 0.0), 0, &options).is_err());
+    }
+
+    #[test]
+    fn circle_spokes_reach_the_rim() {
+        let mut solver = Solver::new(&[], vec2(0.0, -100.0), 400.0, 8, 25.0, 10000.0);
+        let options = BodyOptions { bend_springs: true, ..BodyOptions::default() };
+        let body = solver.create_circle(vec2(0.0, 0.0), 40.0, 8, &options).unwrap();
+
+        assert_eq!(body.len(), 9);
+        let springs = body_springs(&solver, &body);
+        // Spokes, rim, skip 2 and skip 3 around the rim
+        assert_eq!(springs.len(), 8 * 4);
+        let hub = body.get_verlet(0).unwrap();
+        let spokes: Vec<_> = springs.iter().filter(|spring| spring.0 == hub).collect();
+        assert_eq!(spokes.len(), 8);
+        assert!(spokes.iter().all(|spring| (spring.2 - 40.0).abs() < 1e-4));
+        let rim_length = 2.0 * 40.0 * (std::f32::consts::PI / 8.0).sin();
+        assert_eq!(springs.iter().filter(|spring| (spring.2 - rim_length).abs() < 1e-3).count(), 8);
+
+        // Skipping halfway round a square only gives the 2 diagonals, not 4
+        let square = solver.create_circle(vec2(0.0, 0.0), 40.0, 4, &BodyOptions::default()).unwrap();
+        assert_eq!(body_springs(&solver, &square).len(), 4 + 4 + 2);
+
+        assert!(solver.create_circle(vec2(0.0, 0.0), 40.0, 2, &options).is_err());
+    }
+
+    // 120 drops of radius spacing / 2 packed a spacing apart into the bottom of a 100 wide box - rest density is what they weigh per spacing^2
+    fn fluid_tank(spacing: f32, subdivision: usize) -> (Solver, f32) {
+        let mut solver = Solver::new(&[], vec2(0.0, -100.0), 200.0, subdivision, 25.0, 10000.0);
+        solver.set_boundary(Boundary::Box { min: vec2(-50.0, -200.0), max: vec2(50.0, 200.0) });
+        for y in 0..12 {
+            for x in 0..10 {
+                let mut drop = Verlet::new(vec2(-45.0 + x as f32 * spacing, -190.0 + y as f32 * spacing));
+                drop.set_radius(spacing / 2.0);
+                drop.set_fluid(true);
+                solver.add_position(drop);
+            }
+        }
+        let rest_density = solver.get_verlets()[0].get_mass() / (spacing * spacing);
+        (solver, rest_density)
+    }
+
+    // Drops along the walls and the surface have fewer neighbours so only the middle one is checked against rest density
+    fn assert_fluid_settled(solver: &Solver, rest_density: f32) {
+        assert!(solver.get_verlets().iter().all(|verlet| verlet.get_position().is_finite() && verlet.get_velocity().is_finite()));
+        let mut densities: Vec<f32> = solver.get_fluid_densities().iter().map(|density| density / rest_density).collect();
+        assert!(densities.iter().all(|density| density.is_finite()));
+        densities.sort_by(f32::total_cmp);
+        let median = densities[densities.len() / 2];
+        assert!((median - 1.0).abs() < 0.05, "{median}");
+        assert!(densities[densities.len() - 1] < 1.1, "{densities:?}");
+        // Still a puddle at the bottom rather than splashed all over the box
+        let top = solver.get_verlets().iter().map(|verlet| verlet.get_position().y).fold(f32::MIN, f32::max);
+        assert!(top < -80.0, "{top}");
+    }
+
+    #[test]
+    fn sph_settles_at_rest_density() {
+        let (mut solver, rest_density) = fluid_tank(10.0, 8);
+        solver.set_sph(Some(Sph::new(20.0, rest_density, 1.0e6, 200.0)));
+        for _ in 0..1000 {
+            solver.update(0.004);
+        }
+        assert_fluid_settled(&solver, rest_density);
+    }
+
+    #[test]
+    fn fluid_only_skips_hard_contacts_with_fluid() {
+        let mut drop1 = Verlet::new(vec2(0.0, 0.0));
+        drop1.set_fluid(true);
+        let mut drop2 = Verlet::new(vec2(5.0, 0.0));
+        drop2.set_fluid(true);
+        let ball = Verlet::new(vec2(-5.0, 0.0));
+        let mut solver = Solver::new(&[drop1, drop2, ball], vec2(0.0, -100.0), 200.0, 8, 25.0, 10000.0);
+
+        // Without a fluid mode they're just balls
+        assert!(solver.can_collide(0, 1));
+        solver.set_sph(Some(Sph::new(20.0, 1.0, 1.0e6, 200.0)));
+        assert!(!solver.can_collide(0, 1));
+        assert!(solver.can_collide(0, 2));
+        assert!(solver.can_collide(2, 1));
+    }
+
+    // At big steps like it's meant for - lots of tiny substeps splash it about
+    #[test]
+    fn pbf_settles_at_rest_density() {
+        let (mut solver, rest_density) = fluid_tank(10.0, 1);
+        solver.set_pbf(Some(Pbf::new(20.0, rest_density, 4)));
+        for _ in 0..240 {
+            solver.update(1.0 / 60.0);
+        }
+        assert_fluid_settled(&solver, rest_density);
+
+        // Same contact rules as SPH
+        solver.add_position(Verlet::new(vec2(0.0, 0.0)));
+        let ball = solver.get_verlets().len() - 1;
+        assert!(!solver.can_collide(0, 1));
+        assert!(solver.can_collide(0, ball));
+    }
+}
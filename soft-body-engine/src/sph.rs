@@ -0,0 +1,49 @@
+use std::f32::consts::PI;
+
+use glam::Vec2;
+
+// Smoothed particle hydrodynamics - every fluid verlet is a blob of water smeared out over smoothing_radius
+// https://matthias-research.github.io/pages/publications/sca03.pdf with the kernels scaled for 2D
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sph {
+    pub smoothing_radius: f32,
+    pub rest_density: f32, // Mass per area the fluid settles at - verlets spaced d apart come out around mass / d^2
+    pub stiffness: f32, // Pressure = stiffness * (density - rest_density) - higher is less squishy but needs smaller steps
+    pub viscosity: f32,
+}
+
+impl Sph {
+    pub fn new(smoothing_radius: f32, rest_density: f32, stiffness: f32, viscosity: f32) -> Self {
+        Sph { smoothing_radius, rest_density, stiffness, viscosity }
+    }
+
+    // Only pushes - letting it pull when the density drops makes the particles clump up into pairs
+    pub fn pressure(&self, density: f32) -> f32 {
+        (self.stiffness * (density - self.rest_density)).max(0.0)
+    }
+}
+
+// Smooth bump used for the density - takes r^2 so the sqrt can be skipped
+pub fn poly6(dist_squared: f32, smoothing_radius: f32) -> f32 {
+    let h_squared = smoothing_radius * smoothing_radius;
+    if dist_squared >= h_squared {
+        return 0.0;
+    }
+    4.0 / (PI * h_squared.powi(4)) * (h_squared - dist_squared).powi(3)
+}
+
+// Gradient of the spiky kernel at offset from the neighbor - poly6 flattens out in the middle so particles right on top of each other wouldn't push apart
+pub fn spiky_gradient(offset: Vec2, dist: f32, smoothing_radius: f32) -> Vec2 {
+    if dist >= smoothing_radius || dist == 0.0 {
+        return Vec2::ZERO;
+    }
+    -30.0 / (PI * smoothing_radius.powi(5)) * (smoothing_radius - dist).powi(2) * offset / dist
+}
+
+// Laplacian of the viscosity kernel - always positive so viscosity only ever evens out velocities
+pub fn viscosity_laplacian(dist: f32, smoothing_radius: f32) -> f32 {
+    if dist >= smoothing_radius {
+        return 0.0;
+    }
+    40.0 / (PI * smoothing_radius.powi(5)) * (smoothing_radius - dist)
+}
@@ -0,0 +1,163 @@
+use std::time::Instant;
+
+use super::solver::Solver;
+
+// Fixed timestep accumulator - https://www.gafferongames.com/post/fix_your_timestep/
+// Real frame time goes in, a whole number of fixed dt steps comes out and whatever is left over becomes the interpolation alpha
+pub struct Stepper {
+    dt: f64,
+    accumulator: f64,
+    max_steps_per_frame: usize,
+    last_time: Option<Instant>,
+    frame_time: f64,
+    total_time: f64,    // Simulated time so it doesn't include anything dropped
+    dropped_time: f64,  // Real time thrown away because we were too far behind
+    dropped_frames: usize,
+}
+
+impl Stepper {
+    // dt is in seconds - max_steps_per_frame is the cap that stops the spiral of death
+    pub fn new(dt: f64, max_steps_per_frame: usize) -> Result<Self, String> {
+        check_dt(dt)?;
+        Ok(Stepper {
+            dt,
+            accumulator: 0.0,
+            max_steps_per_frame: max_steps_per_frame.max(1),
+            last_time: None,
+            frame_time: 0.0,
+            total_time: 0.0,
+            dropped_time: 0.0,
+            dropped_frames: 0,
+        })
+    }
+
+    // Measures the real time since the last tick and returns how many steps to run
+    pub fn tick(&mut self) -> usize {
+        let now = Instant::now();
+        let frame_time = match self.last_time {
+            Some(last_time) => now.duration_since(last_time).as_secs_f64(),
+            None => 0.0,
+        };
+        self.last_time = Some(now);
+        self.advance(frame_time)
+    }
+
+    // Same as tick but with a frame time you pick - handy for replays or running faster than real time
+    pub fn advance(&mut self, frame_time: f64) -> usize {
+        self.frame_time = frame_time;
+        self.accumulator += frame_time;
+
+        let mut steps = (self.accumulator / self.dt).floor() as usize;
+        if steps > self.max_steps_per_frame {
+            // If one frame takes longer than it simulates we fall further behind every frame
+            // So drop the whole steps we can't afford and keep the leftover fraction for alpha
+            let dropped_steps = steps - self.max_steps_per_frame;
+            self.dropped_time += dropped_steps as f64 * self.dt;
+            self.dropped_frames += 1;
+            self.accumulator -= dropped_steps as f64 * self.dt;
+            steps = self.max_steps_per_frame;
+        }
+
+        self.accumulator -= steps as f64 * self.dt;
+        self.total_time += steps as f64 * self.dt;
+        steps
+    }
+
+    // Tick and run the solver for every step
+    pub fn step(&mut self, solver: &mut Solver) -> usize {
+        let steps = self.tick();
+        for _ in 0..steps {
+            solver.update(self.get_dt());
+        }
+        steps
+    }
+
+    pub fn get_dt(&self) -> f32 {
+        self.dt as f32
+    }
+
+    #[allow(dead_code)]
+    pub fn set_dt(&mut self, dt: f64) -> Result<(), String> {
+        check_dt(dt)?;
+        self.dt = dt;
+        Ok(())
+    }
+
+    // How far we are between the last step and the next one for get_interpolated_position
+    pub fn get_alpha(&self) -> f32 {
+        (self.accumulator / self.dt) as f32
+    }
+
+    pub fn get_frame_time(&self) -> f64 {
+        self.frame_time
+    }
+
+    pub fn get_total_time(&self) -> f64 {
+        self.total_time
+    }
+
+    pub fn get_dropped_time(&self) -> f64 {
+        self.dropped_time
+    }
+
+    #[allow(dead_code)]
+    pub fn get_dropped_frames(&self) -> usize {
+        self.dropped_frames
+    }
+
+    #[allow(dead_code)]
+    pub fn get_max_steps_per_frame(&self) -> usize {
+        self.max_steps_per_frame
+    }
+
+    #[allow(dead_code)]
+    pub fn set_max_steps_per_frame(&mut self, max_steps_per_frame: usize) {
+        self.max_steps_per_frame = max_steps_per_frame.max(1);
+    }
+}
+
+// Steps that take no time would never use up the accumulator
+fn check_dt(dt: f64) -> Result<(), String> {
+    if !(dt > 0.0 && dt.is_finite()) {
+        return Err::<(), String>(String::from("dt has to be positive"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dt_has_to_be_positive() {
+        assert!(Stepper::new(0.0, 4).is_err());
+        assert!(Stepper::new(-0.01, 4).is_err());
+        assert!(Stepper::new(f64::NAN, 4).is_err());
+        let mut stepper = Stepper::new(0.01, 4).unwrap();
+        assert!(stepper.set_dt(0.0).is_err());
+        assert_eq!(stepper.get_dt(), 0.01);
+    }
+
+    #[test]
+    fn leftover_time_becomes_alpha() {
+        let mut stepper = Stepper::new(0.01, 4).unwrap();
+        assert_eq!(stepper.advance(0.025), 2);
+        assert!((stepper.get_alpha() - 0.5).abs() < 1e-6);
+        // The leftover carries over into the next frame
+        assert_eq!(stepper.advance(0.005), 1);
+        assert!(stepper.get_alpha().abs() < 1e-6);
+        assert!((stepper.get_total_time() - 0.03).abs() < 1e-12);
+    }
+
+    #[test]
+    fn catching_up_is_capped_and_the_rest_dropped() {
+        let mut stepper = Stepper::new(0.01, 4).unwrap();
+        assert_eq!(stepper.advance(0.105), 4);
+        assert!((stepper.get_dropped_time() - 0.06).abs() < 1e-12);
+        assert_eq!(stepper.get_dropped_frames(), 1);
+        // Only the fraction of a step is kept so the next frame isn't behind as well
+        assert!((stepper.get_alpha() - 0.5).abs() < 1e-6);
+        assert_eq!(stepper.advance(0.01), 1);
+        assert!((stepper.get_total_time() - 0.05).abs() < 1e-12);
+    }
+}
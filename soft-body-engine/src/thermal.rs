@@ -0,0 +1,64 @@
+use glam::{Vec2, Vec4, vec4};
+
+// Heat moving between touching verlets and the walls - hot verlets get lighter and float up so a heated floor makes convection cells
+// Temperature is in whatever units you like as long as the walls, ambient_temperature and expansion agree
+#[derive(Clone, Debug, PartialEq)]
+pub struct Thermal {
+    pub conductivity: f32, // How fast two touching verlets even out - roughly the fraction of the gap closed per second
+    pub ambient_temperature: f32, // Temperature nothing floats or sinks at
+    pub cooling: f32, // How fast everything drifts back to ambient_temperature like it's losing heat to air - 0 is perfectly insulated
+    pub expansion: f32, // Boussinesq - a verlet 1 degree over ambient gets pushed against gravity by expansion * |gravity|
+    pub wake_difference: f32, // Sleeping verlets can't conduct so one touching a wall or a verlet at least this much hotter or colder wakes up
+    pub wall_heat: Vec<WallHeat>,
+}
+
+// Walls facing direction hold temperature and heat up or cool down whatever is within reach of them
+// direction is the way out of the container so (0, -1) is the floor and (0, 1) the ceiling
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WallHeat {
+    pub direction: Vec2,
+    pub temperature: f32,
+    pub transfer: f32, // Same as conductivity but between a verlet and the wall
+    pub reach: f32, // Gap past touching that still gets heated - with 0 a hot verlet floats off the floor and stops heating right away
+}
+
+// Walls whose normal is within 45 degrees of a heater's direction count as that heater
+const WALL_HEAT_COS_ANGLE: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+impl Thermal {
+    pub fn new(conductivity: f32, ambient_temperature: f32, expansion: f32) -> Self {
+        Thermal {
+            conductivity,
+            ambient_temperature,
+            cooling: 0.0,
+            expansion,
+            wake_difference: 1.0,
+            wall_heat: vec![],
+        }
+    }
+
+    pub fn add_wall_heat(&mut self, direction: Vec2, temperature: f32, transfer: f32, reach: f32) {
+        self.wall_heat.push(WallHeat { direction: direction.normalize_or_zero(), temperature, transfer, reach });
+    }
+
+    // The heater for a wall with this outward normal if there is one
+    pub fn wall_heat_at(&self, wall_normal: Vec2) -> Option<&WallHeat> {
+        self.wall_heat.iter().find(|wall_heat| wall_heat.direction.dot(wall_normal) >= WALL_HEAT_COS_ANGLE)
+    }
+
+    // Extra acceleration from being hotter or colder than ambient - hot things go the opposite way to gravity
+    pub fn buoyancy(&self, temperature: f32, gravity: Vec2) -> Vec2 {
+        -gravity * self.expansion * (temperature - self.ambient_temperature)
+    }
+}
+
+// Blue at cold through red to yellow at hot in the 0 - 255 colors verlets use
+pub fn temperature_color(temperature: f32, cold: f32, hot: f32) -> Vec4 {
+    let t = ((temperature - cold) / (hot - cold)).clamp(0.0, 1.0);
+    let (blue, red, yellow) = (vec4(40.0, 60.0, 230.0, 1.0), vec4(230.0, 40.0, 30.0, 1.0), vec4(255.0, 230.0, 60.0, 1.0));
+    if t < 0.5 {
+        blue.lerp(red, t * 2.0)
+    } else {
+        red.lerp(yellow, t * 2.0 - 1.0)
+    }
+}
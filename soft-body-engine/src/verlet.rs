@@ -1,4 +1,5 @@
 use glam::{Vec2, Vec4, vec4};
+use super::integrator::Integrator;
 
 #[derive(Clone, Debug)]
 pub struct Verlet {
@@ -11,6 +12,16 @@ pub struct Verlet {
     last_dt: f32,
     color: Vec4,
     anchored: bool,
+    rigid_body: Option<usize>,
+    collision_group: u32,
+    collision_layer: u32,
+    collision_mask: u32,
+    continuous_collision: bool,
+    sleeping: bool,
+    sleep_timer: f32,
+    charge: f32,
+    fluid: bool,
+    temperature: Option<f32>, // None until something sets it - the solver starts it at the ambient temperature
 }
 
 impl Verlet {
@@ -25,6 +36,16 @@ impl Verlet {
             last_dt: 0.0,
             color: vec4(255.0, 255.0, 255.0, 1.0),
             anchored: false,
+            rigid_body: None,
+            collision_group: 0,
+            collision_layer: 1,
+            collision_mask: u32::MAX,
+            continuous_collision: false,
+            sleeping: false,
+            sleep_timer: 0.0,
+            charge: 0.0,
+            fluid: false,
+            temperature: None,
         }
     }
     
@@ -44,10 +65,135 @@ impl Verlet {
         self.radius = radius;
     }
 
+    pub fn is_anchored(&self) -> bool {
+        self.anchored
+    }
+
+    pub fn set_anchored(&mut self, anchored: bool) {
+        self.anchored = anchored;
+        self.last_position = self.position;
+    }
+
+    // A sleeping verlet is frozen in place until something wakes it up - the solver decides when
+    pub fn is_sleeping(&self) -> bool {
+        self.sleeping
+    }
+
+    pub fn set_sleeping(&mut self, sleeping: bool) {
+        self.sleeping = sleeping;
+        self.sleep_timer = 0.0;
+        self.last_position = self.position;
+    }
+
+    // How long the verlet has been slow enough to fall asleep
+    pub fn get_sleep_timer(&self) -> f32 {
+        self.sleep_timer
+    }
+
+    pub fn set_sleep_timer(&mut self, sleep_timer: f32) {
+        self.sleep_timer = sleep_timer;
+    }
+
+    // Anchored and sleeping verlets both don't move so collisions treat them like walls with infinite mass
+    pub fn is_static(&self) -> bool {
+        self.anchored || self.sleeping
+    }
+
+    pub fn get_rigid_body(&self) -> Option<usize> {
+        self.rigid_body
+    }
+
+    pub fn set_rigid_body(&mut self, rigid_body: Option<usize>) {
+        self.rigid_body = rigid_body;
+    }
+
+    // 0 means no group, verlets sharing any other group never collide with each other
+    pub fn get_collision_group(&self) -> u32 {
+        self.collision_group
+    }
+
+    pub fn set_collision_group(&mut self, collision_group: u32) {
+        self.collision_group = collision_group;
+    }
+
+    // Bits for the layers this verlet is on
+    #[allow(dead_code)]
+    pub fn get_collision_layer(&self) -> u32 {
+        self.collision_layer
+    }
+
+    pub fn set_collision_layer(&mut self, collision_layer: u32) {
+        self.collision_layer = collision_layer;
+    }
+
+    // Bits for the layers this verlet is allowed to hit
+    pub fn get_collision_mask(&self) -> u32 {
+        self.collision_mask
+    }
+
+    pub fn set_collision_mask(&mut self, collision_mask: u32) {
+        self.collision_mask = collision_mask;
+    }
+
+    // Both have to accept each other so a ghost with mask 0 never gets hit even by something that hits everything
+    pub fn collides_with(&self, other: &Verlet) -> bool {
+        (self.collision_layer & other.collision_mask) != 0 && (other.collision_layer & self.collision_mask) != 0
+    }
+
+    // Opt in swept collision tests for fast verlets that could skip through things in one substep
+    pub fn is_continuous_collision(&self) -> bool {
+        self.continuous_collision
+    }
+
+    pub fn set_continuous_collision(&mut self, continuous_collision: bool) {
+        self.continuous_collision = continuous_collision;
+    }
+
+    // Mass per area - get_mass is density * area
+    #[allow(dead_code)]
+    pub fn get_density(&self) -> f32 {
+        self.density
+    }
+
+    pub fn set_density(&mut self, density: f32) {
+        self.density = density;
+    }
+
     pub fn get_mass(&self) -> f32 {
         self.density * std::f32::consts::PI * self.radius * self.radius
     }
 
+    // Only matters once the solver has coulomb or a magnetic field turned on - same signs push apart
+    pub fn get_charge(&self) -> f32 {
+        self.charge
+    }
+
+    pub fn set_charge(&mut self, charge: f32) {
+        self.charge = charge;
+    }
+
+    // Fluid verlets get pushed around by the solver's fluid pressure instead of bumping into each other
+    pub fn is_fluid(&self) -> bool {
+        self.fluid
+    }
+
+    pub fn set_fluid(&mut self, fluid: bool) {
+        self.fluid = fluid;
+    }
+
+    // Only changes while the solver has thermal turned on - heat capacity goes with the mass
+    pub fn get_temperature(&self) -> f32 {
+        self.temperature.unwrap_or(0.0)
+    }
+
+    pub fn set_temperature(&mut self, temperature: f32) {
+        self.temperature = Some(temperature);
+    }
+
+    pub fn has_temperature(&self) -> bool {
+        self.temperature.is_some()
+    }
+
     pub fn add_acceleration(&mut self, acceleration: Vec2){
         self.acceleration += acceleration;
     }
@@ -56,6 +202,10 @@ impl Verlet {
         self.position 
     }
 
+    pub fn get_last_position(&self) -> Vec2 {
+        self.last_position
+    }
+
     pub fn get_velocity(&self) -> Vec2 {
         if self.last_dt == 0.0 {
             Vec2::ZERO
@@ -64,18 +214,28 @@ impl Verlet {
         }
     }
 
+    // The velocity is stored as the step back to last_position so we also have to remember which dt that step was for
     pub fn set_velocity(&mut self, velocity: Vec2, dt: f32) {
         self.last_position = self.position - velocity * dt;
+        self.last_dt = dt;
     }
 
     pub fn add_velocity(&mut self, velocity: Vec2, dt: f32) {
-        self.last_position -= velocity * dt;
+        let velocity = self.get_velocity() + velocity;
+        self.set_velocity(velocity, dt);
     }
 
     pub fn set_position(&mut self, position: Vec2) {
         self.position = position;
     }
 
+    // Moves the verlet without touching its velocity - like when it wraps around a periodic boundary
+    pub fn translate(&mut self, offset: Vec2) {
+        self.position += offset;
+        self.last_position += offset;
+    }
+
+    #[allow(dead_code)]
     pub fn get_acceleration(&self) -> Vec2 {
         self.last_acceleration
     }
@@ -84,8 +244,85 @@ impl Verlet {
         self.last_position + (self.position - self.last_position) * alpha
     }
 
+    // field is the acceleration that depends on where the verlet is and how fast it's going (gravity, force fields)
+    // Anything already added with add_acceleration (springs etc.) is held constant over the step
+    pub fn integrate(&mut self, dt: f32, integrator: Integrator, field: &dyn Fn(Vec2, Vec2) -> Vec2) {
+        // Nothing moves these so there's no point sampling the field
+        if self.is_static() {
+            self.update_position(dt);
+            return;
+        }
+
+        let position = self.position;
+        let velocity = self.get_velocity();
+        let constant = self.acceleration;
+
+        // Each one gives back the new position, new velocity and the acceleration we remember for next step
+        let (new_position, new_velocity, last_acceleration) = match integrator {
+            Integrator::PositionVerlet => {
+                self.acceleration += field(position, velocity);
+                self.update_position(dt);
+                return;
+            }
+            Integrator::SemiImplicitEuler => {
+                let acceleration = constant + field(position, velocity);
+                let new_velocity = velocity + acceleration * dt;
+                (position + new_velocity * dt, new_velocity, acceleration)
+            }
+            Integrator::VelocityVerlet => {
+                // The field gets sampled again at the new position for the second half of the kick
+                // Springs and other added accelerations are only known here so they count for the whole step
+                let acceleration = constant + field(position, velocity);
+                let new_position = position + velocity * dt + 0.5 * acceleration * dt * dt;
+                let new_acceleration = constant + field(new_position, velocity + acceleration * dt);
+                let average = 0.5 * (acceleration + new_acceleration);
+                (new_position, velocity + average * dt, average)
+            }
+            Integrator::Rk4 => {
+                let acceleration = |x: Vec2, v: Vec2| constant + field(x, v);
+
+                let k1_x = velocity;
+                let k1_v = acceleration(position, velocity);
+                let k2_x = velocity + k1_v * dt / 2.0;
+                let k2_v = acceleration(position + k1_x * dt / 2.0, k2_x);
+                let k3_x = velocity + k2_v * dt / 2.0;
+                let k3_v = acceleration(position + k2_x * dt / 2.0, k3_x);
+                let k4_x = velocity + k3_v * dt;
+                let k4_v = acceleration(position + k3_x * dt, k4_x);
+
+                let new_velocity = velocity + (k1_v + 2.0 * k2_v + 2.0 * k3_v + k4_v) * dt / 6.0;
+                (
+                    position + (k1_x + 2.0 * k2_x + 2.0 * k3_x + k4_x) * dt / 6.0,
+                    new_velocity,
+                    (new_velocity - velocity) / dt,
+                )
+            }
+        };
+
+        // Velocity is still stored as the step back to last_position so the collision code works the same for every integrator
+        // That makes last_position the straight line estimate x' - v' dt instead of exactly where we were
+        self.position = new_position;
+        self.set_velocity(new_velocity, dt);
+        self.last_acceleration = last_acceleration;
+        self.acceleration = Vec2::ZERO;
+    }
+
     pub fn update_position(&mut self, dt: f32){
-        let displacement = self.position - self.last_position;
+        // Anchored and sleeping verlets ignore every force but still count as having been updated
+        if self.is_static() {
+            self.last_position = self.position;
+            self.last_acceleration = Vec2::ZERO;
+            self.last_dt = dt;
+            self.acceleration = Vec2::ZERO;
+            return;
+        }
+
+        // Time corrected verlet - the last displacement happened over last_dt so scale it to this dt
+        // Otherwise changing dt between steps speeds up or slows down everything and injects energy
+        let mut displacement = self.position - self.last_position;
+        if self.last_dt > 0.0 {
+            displacement *= dt / self.last_dt;
+        }
         self.last_position = self.position;
 
         self.position += self.acceleration * dt * dt;